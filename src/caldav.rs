@@ -0,0 +1,543 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use native_tls::TlsConnector;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config, get_time, ical, journal,
+    merge::{self, Side, Strategy},
+    store::Store,
+    Task,
+};
+
+// the variable the password is read from, before the caldav-password setting
+const PASSWORD_VAR: &str = "PLS_CALDAV_PASSWORD";
+
+// where what was last synced is kept in the database
+const STATE: &str = "caldav-state";
+
+// asks for every VTODO of the collection, with its ETag
+const QUERY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:prop>
+    <d:getetag/>
+    <c:calendar-data/>
+  </d:prop>
+  <c:filter>
+    <c:comp-filter name="VCALENDAR">
+      <c:comp-filter name="VTODO"/>
+    </c:comp-filter>
+  </c:filter>
+</c:calendar-query>
+"#;
+
+/// The collection to sync with, and who to log in as.
+pub struct Account {
+    pub url: String,
+    user: String,
+    password: String,
+}
+
+impl Account {
+    /// The account from the caldav-url and caldav-user settings, after saving the ones given,
+    /// and the password from `PLS_CALDAV_PASSWORD` or the caldav-password setting. An http://
+    /// URL is refused unless the caldav-allow-http setting is true.
+    pub fn new(
+        db: &mut dyn Store,
+        url: Option<&String>,
+        user: Option<&String>,
+    ) -> Result<Self, String> {
+        for (key, value) in [("caldav-url", url), ("caldav-user", user)] {
+            if let Some(value) = value {
+                config::set(db, key, value)?;
+            }
+        }
+        let missing =
+            || "CalDAV sync isn't set up, run `pls sync caldav --url <URL> --user <USER>`";
+        let url = db.setting("caldav-url").ok_or_else(missing)?;
+        let user = db.setting("caldav-user").ok_or_else(missing)?;
+        // basic auth sends the password as it is, for anyone on the way to read without TLS
+        if url.starts_with("http://") && db.setting("caldav-allow-http").as_deref() != Some("true")
+        {
+            return Err(format!("{url} would send the CalDAV password unencrypted, use https:// or run `pls config caldav-allow-http true`"));
+        }
+        let password = std::env::var(PASSWORD_VAR)
+            .ok()
+            .or_else(|| db.setting("caldav-password"))
+            .ok_or_else(|| {
+                format!("no CalDAV password, set {PASSWORD_VAR} or run `pls config caldav-password <PASSWORD>`")
+            })?;
+        Ok(Self {
+            url,
+            user,
+            password,
+        })
+    }
+}
+
+/// What a sync changed, on both sides.
+pub struct Synced {
+    pub before: Vec<Task>,
+    pub after: Vec<Task>,
+    pub uploaded: usize,
+    pub deleted: usize,
+    // tasks changed on both sides, or changed on the server during the sync
+    pub conflicts: usize,
+    // the href of each resource that couldn't be read, and why
+    pub skipped: Vec<(String, String)>,
+}
+
+// a task as it was after the last sync, with where it is on the server and its ETag then
+#[derive(Serialize, Deserialize, Clone)]
+struct Resource {
+    href: String,
+    etag: String,
+    task: Task,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct State {
+    url: String,
+    resources: Vec<Resource>,
+}
+
+// a VTODO resource found on the server
+#[derive(Default, Debug)]
+struct Remote {
+    href: String,
+    etag: String,
+    data: String,
+}
+
+/// Pull the tasks changed on the server since the last sync, merge them with the local tasks
+/// task by task, and push the local changes back. Tasks whose etag is the same as on the last
+/// sync are taken as unchanged, and only tasks that changed here are uploaded, on the condition
+/// that the server still has the version they were merged with. The change to the local tasks
+/// is recorded in the journal.
+pub fn sync(db: &mut dyn Store, account: &Account, command: &str) -> Result<Synced, String> {
+    let client = Client::new(account)?;
+    let local = db.tasks();
+    // what was synced with another collection doesn't tell anything about this one
+    let state = db
+        .get::<State>(STATE)
+        .filter(|s| s.url == account.url)
+        .unwrap_or_default();
+    let base = state
+        .resources
+        .iter()
+        .map(|r| r.task.clone())
+        .collect::<Vec<_>>();
+
+    let offset = get_time().offset();
+    // a resource that can't be read is skipped rather than failing the whole sync, and the task
+    // it had when it was last synced is taken as unchanged on the server
+    let (mut remote, mut skipped) = (Vec::new(), Vec::new());
+    for r in client.query()? {
+        match ical::import(&r.data, offset) {
+            Ok(tasks) => remote.push((r, tasks)),
+            Err(e) => skipped.push((r.href, e)),
+        }
+    }
+    let unreadable = state
+        .resources
+        .iter()
+        .filter(|r| skipped.iter().any(|(href, _)| *href == r.href))
+        .collect::<Vec<_>>();
+    // every resource is a calendar of its own, read together so that subtasks find their parent
+    let calendar = remote
+        .iter()
+        .map(|(r, _)| r.data.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let imported = ical::import(&calendar, offset)?;
+    let resources = remote
+        .iter()
+        .flat_map(|(r, tasks)| tasks.iter().map(move |task| (ical::uid(task), r)))
+        .collect::<HashMap<_, _>>();
+
+    // tasks the server has get the id they have here, or the next free one
+    let known = |uid: &str| {
+        base.iter()
+            .chain(&local)
+            .find(|t| ical::uid(t) == uid)
+            .cloned()
+    };
+    let mut next_id = local
+        .iter()
+        .chain(&base)
+        .map(|t| t.id + 1)
+        .chain(db.get::<u64>("task-id"))
+        .max()
+        .unwrap_or(1);
+    let ids = imported
+        .iter()
+        .map(|t| {
+            let id = known(&ical::uid(t)).map_or_else(
+                || {
+                    next_id += 1;
+                    next_id - 1
+                },
+                |k| k.id,
+            );
+            (t.id, id)
+        })
+        .collect::<HashMap<_, _>>();
+    let mut theirs = imported
+        .iter()
+        .map(|t| {
+            let uid = ical::uid(t);
+            let synced = state.resources.iter().find(|r| ical::uid(&r.task) == uid);
+            match synced {
+                // unchanged since the last sync
+                Some(synced) if resources.get(&uid).map(|r| &r.etag) == Some(&synced.etag) => {
+                    synced.task.clone()
+                }
+                // the server only has some of a task's fields, the others stay as they are here
+                _ => Task {
+                    id: ids[&t.id],
+                    parent: t.parent.and_then(|p| ids.get(&p).copied()),
+                    ..known(&uid).map_or_else(|| t.clone(), |k| update(k, t))
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    theirs.extend(unreadable.iter().map(|r| r.task.clone()));
+
+    let mut our_removed = merge::tombstones(db);
+    our_removed.extend(merge::removed(&base, &local));
+    let their_removed = merge::removed(&base, &theirs);
+    let merged = merge::merge(
+        &base,
+        &[],
+        &Side {
+            tasks: &local,
+            tombstones: &our_removed,
+        },
+        &Side {
+            tasks: &theirs,
+            tombstones: &their_removed,
+        },
+        Strategy::Newest,
+    );
+
+    let now = get_time();
+    let mut synced = Synced {
+        before: local.clone(),
+        after: merged.tasks.clone(),
+        uploaded: 0,
+        deleted: 0,
+        conflicts: merged.conflicts.len(),
+        skipped,
+    };
+    let mut state = State {
+        url: account.url.clone(),
+        resources: Vec::new(),
+    };
+    for task in &merged.tasks {
+        let uid = ical::uid(task);
+        // left as it is until the server has a version that can be read
+        if let Some(r) = unreadable.iter().find(|r| ical::uid(&r.task) == uid) {
+            state.resources.push((*r).clone());
+            continue;
+        }
+        let on_server = resources.get(&uid);
+        let (href, etag) = match on_server {
+            Some(r) if theirs.iter().find(|t| ical::uid(t) == uid) == Some(task) => {
+                (r.href.clone(), r.etag.clone())
+            }
+            _ => {
+                let href = on_server.map_or_else(|| client.href(&uid), |r| r.href.clone());
+                let calendar = ical::export_one(task, &merged.tasks, now);
+                let Some(etag) =
+                    client.put(&href, on_server.map(|r| r.etag.as_str()), &calendar)?
+                else {
+                    // changed on the server since it was read, it is merged on the next sync
+                    synced.conflicts += 1;
+                    continue;
+                };
+                synced.uploaded += 1;
+                (href, etag)
+            }
+        };
+        state.resources.push(Resource {
+            href,
+            etag,
+            task: task.clone(),
+        });
+    }
+    for (uid, r) in &resources {
+        if !merged.tasks.iter().any(|t| ical::uid(t) == *uid) {
+            if client.delete(&r.href, &r.etag)? {
+                synced.deleted += 1;
+            } else {
+                synced.conflicts += 1;
+            }
+        }
+    }
+    db.set(STATE, &state)?;
+
+    if merged.tasks != local {
+        journal::save_tasks(db, command, &local, &merged.tasks);
+        merge::skip_ids(db, &merged.tasks).map_err(|e| format!("Failed to set task id: {e}"))?;
+    }
+    Ok(synced)
+}
+
+// a task with the fields a VTODO has taken from the server's version
+fn update(task: Task, remote: &Task) -> Task {
+    Task {
+        title: remote.title.clone(),
+        completed: remote.completed,
+        due: remote.due,
+        priority: remote.priority,
+        tags: remote.tags.clone(),
+        project: remote.project.clone(),
+        recurrence: remote.recurrence,
+        created_at: remote.created_at.or(task.created_at),
+        completed_at: remote.completed_at,
+        updated_at: remote.updated_at,
+        uid: remote.uid.clone(),
+        ..task
+    }
+}
+
+struct Client<'a> {
+    agent: ureq::Agent,
+    account: &'a Account,
+}
+
+impl<'a> Client<'a> {
+    fn new(account: &'a Account) -> Result<Self, String> {
+        let connector = TlsConnector::new().map_err(|e| format!("Failed to set up TLS: {e}"))?;
+        let agent = ureq::AgentBuilder::new()
+            .tls_connector(Arc::new(connector))
+            .timeout(Duration::from_secs(30))
+            .build();
+        Ok(Self { agent, account })
+    }
+
+    fn request(&self, method: &str, href: &str) -> ureq::Request {
+        let credentials = format!("{}:{}", self.account.user, self.account.password);
+        self.agent.request(method, &self.resolve(href)).set(
+            "Authorization",
+            &format!("Basic {}", base64(credentials.as_bytes())),
+        )
+    }
+
+    // every VTODO of the collection
+    fn query(&self) -> Result<Vec<Remote>, String> {
+        let error = |e: &dyn std::fmt::Display| {
+            format!("Failed to get tasks from {}: {e}", self.account.url)
+        };
+        let xml = self
+            .request("REPORT", &self.account.url)
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(QUERY)
+            .map_err(|e| error(&e))?
+            .into_string()
+            .map_err(|e| error(&e))?;
+        Ok(parse_multistatus(&xml))
+    }
+
+    // upload a task, replacing the version with the given ETag, or creating it if there is none.
+    // returns the new ETag, none if the server has another version by now
+    fn put(
+        &self,
+        href: &str,
+        etag: Option<&str>,
+        calendar: &str,
+    ) -> Result<Option<String>, String> {
+        let request = self
+            .request("PUT", href)
+            .set("Content-Type", "text/calendar; charset=utf-8");
+        let request = match etag {
+            Some("") => request,
+            Some(etag) => request.set("If-Match", etag),
+            None => request.set("If-None-Match", "*"),
+        };
+        match request.send_string(calendar) {
+            // not every server gives the new ETag, but the next upload needs it to be safe
+            Ok(response) => response
+                .header("ETag")
+                .map_or_else(|| self.etag(href), |etag| Ok(etag.to_string()))
+                .map(Some),
+            Err(ureq::Error::Status(412, _)) => Ok(None),
+            Err(e) => Err(format!("Failed to upload {href}: {e}")),
+        }
+    }
+
+    // the ETag of a task, read again after an upload that didn't give it
+    fn etag(&self, href: &str) -> Result<String, String> {
+        let response = self
+            .request("GET", href)
+            .call()
+            .map_err(|e| format!("Failed to get {href}: {e}"))?;
+        response
+            .header("ETag")
+            .map(str::to_string)
+            .ok_or_else(|| format!("The server gave no ETag for {href}"))
+    }
+
+    // delete a task, if the server still has the version with the given ETag
+    fn delete(&self, href: &str, etag: &str) -> Result<bool, String> {
+        let request = self.request("DELETE", href);
+        let request = if etag.is_empty() {
+            request
+        } else {
+            request.set("If-Match", etag)
+        };
+        match request.call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(true),
+            Err(ureq::Error::Status(412, _)) => Ok(false),
+            Err(e) => Err(format!("Failed to delete {href}: {e}")),
+        }
+    }
+
+    // where a new task is uploaded to
+    fn href(&self, uid: &str) -> String {
+        let name = uid
+            .bytes()
+            .map(|b| {
+                if b.is_ascii_alphanumeric() || b"-._~@".contains(&b) {
+                    char::from(b).to_string()
+                } else {
+                    format!("%{b:02X}")
+                }
+            })
+            .collect::<String>();
+        format!("{}/{name}.ics", self.account.url.trim_end_matches('/'))
+    }
+
+    // the URL of an href, which servers give as a path
+    fn resolve(&self, href: &str) -> String {
+        let url = &self.account.url;
+        if href.starts_with("http://") || href.starts_with("https://") {
+            return href.to_string();
+        }
+        let host = url.find("://").map_or(0, |i| i + 3);
+        let origin = url[host..].find('/').map_or(url.len(), |i| host + i);
+        if href.starts_with('/') {
+            format!("{}{href}", &url[..origin])
+        } else {
+            format!("{}/{href}", url.trim_end_matches('/'))
+        }
+    }
+}
+
+// the href, ETag and calendar data of every response of a multistatus, skipping the ones without
+// calendar data, ex: the collection itself
+fn parse_multistatus(xml: &str) -> Vec<Remote> {
+    let mut found = Vec::new();
+    let mut current: Option<Remote> = None;
+    // the innermost element the text is in, without its namespace prefix
+    let mut element = String::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        if let Some(remote) = current.as_mut() {
+            let text = unescape(&rest[..start]);
+            match element.as_str() {
+                "href" => remote.href.push_str(&text),
+                "getetag" => remote.etag.push_str(&text),
+                "calendar-data" => remote.data.push_str(&text),
+                _ => {}
+            }
+        }
+        rest = &rest[start..];
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            if let Some(remote) = current.as_mut().filter(|_| element == "calendar-data") {
+                remote.data.push_str(&cdata[..end]);
+            }
+            rest = cdata.get(end + 3..).unwrap_or_default();
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with(['?', '!']) {
+            continue;
+        }
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let name = name.rsplit(':').next().unwrap_or(name);
+        match (name, tag.starts_with('/')) {
+            ("response", false) => current = Some(Remote::default()),
+            ("response", true) => found.extend(current.take()),
+            (name, false) if !tag.ends_with('/') => element = name.to_string(),
+            _ => element.clear(),
+        }
+    }
+    found
+        .into_iter()
+        .filter(|r| !r.data.trim().is_empty())
+        .map(|r| Remote {
+            href: r.href.trim().to_string(),
+            etag: r.etag.trim().to_string(),
+            data: r.data,
+        })
+        .collect()
+}
+
+// replace XML entities, ex: &amp; or &#13;
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .map_or_else(
+                    || entity.strip_prefix('#').and_then(|n| n.parse().ok()),
+                    |n| u32::from_str_radix(n, 16).ok(),
+                )
+                .and_then(char::from_u32),
+        };
+        if let Some(character) = character {
+            unescaped.push(character);
+            rest = &rest[end + 1..];
+        } else {
+            unescaped.push('&');
+            rest = &rest[1..];
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// for the Authorization header
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (u32::from(*b) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,450 @@
+use std::path::Path;
+
+use crate::{
+    filter,
+    priority::{self, Priority},
+    Task,
+};
+
+/// The two kinds of checklist file a task list can be linked to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Style {
+    // `- [ ] title` and `- [x] title` lines, nested by indentation
+    Markdown,
+    // `* TODO title` and `* DONE title` headlines, nested by level
+    Org,
+}
+
+impl Style {
+    /// Org for .org files, Markdown for anything else, ex: TODO.md or todo.txt.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("org") => Self::Org,
+            _ => Self::Markdown,
+        }
+    }
+}
+
+/// A Markdown or Org file read as a task list. Only the lines of the tasks are ever rewritten,
+/// so everything else in the file, such as notes between the tasks, stays as it is.
+#[derive(Clone, Debug)]
+pub struct Checklist {
+    style: Style,
+    lines: Vec<String>,
+    // whether the file ended with a line break
+    newline: bool,
+}
+
+// a task line of the file
+#[derive(Clone, Debug)]
+struct Item {
+    // position in the file
+    line: usize,
+    // indentation or number of stars
+    level: usize,
+    // position in the list of items
+    parent: Option<usize>,
+    // everything before the checkbox or the TODO keyword, ex: "  - " or "** "
+    prefix: String,
+    done: bool,
+    text: String,
+}
+
+// a line of the file that tasks can be nested in
+enum Line {
+    Task {
+        level: usize,
+        prefix: String,
+        done: bool,
+        text: String,
+    },
+    // an Org headline that isn't a task, which ends the tasks above it at its level
+    Heading(usize),
+}
+
+impl Line {
+    const fn level(&self) -> usize {
+        match self {
+            Self::Task { level, .. } | Self::Heading(level) => *level,
+        }
+    }
+}
+
+impl Checklist {
+    pub fn parse(text: &str, style: Style) -> Self {
+        Self {
+            style,
+            lines: text.lines().map(str::to_string).collect(),
+            newline: text.is_empty() || text.ends_with('\n'),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = self.lines.join("\n");
+        if self.newline && !self.lines.is_empty() {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// The tasks in the file, with their position in it as their id, counting from 1.
+    pub fn tasks(&self) -> Vec<Task> {
+        self.items()
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let (title, priority, tags, project) = self.parse_text(&item.text);
+                Task {
+                    id: index as u64 + 1,
+                    title,
+                    completed: item.done,
+                    priority,
+                    tags,
+                    project,
+                    parent: item.parent.map(|parent| parent as u64 + 1),
+                    ..Task::default()
+                }
+            })
+            .collect()
+    }
+
+    /// Change the file to hold `tasks`, where the task with id N is the file's Nth task. Lines of
+    /// tasks that haven't changed are left exactly as they were, removed tasks lose their line
+    /// (and in Org, the text below their headline), and new tasks go after the last task, or
+    /// after the last subtask of their parent. Subtasks of a removed task go after the last task
+    /// too, rather than ending up below the task above them. Only the title, status, priority,
+    /// tags, project and nesting of a task are kept in the file, see `unsupported`.
+    pub fn update(&mut self, tasks: &[Task]) {
+        let before = self.tasks();
+        let items = self.items();
+        // a task stays on its line if its parent does, in file order so parents come first
+        let mut in_place = Vec::with_capacity(items.len());
+        for old in &before {
+            let kept = tasks.iter().find(|t| t.id == old.id).is_some_and(|task| {
+                // a subtask whose parent was removed is a task of its own
+                let parent = task
+                    .parent
+                    .filter(|&parent| tasks.iter().any(|t| t.id == parent));
+                let parent_in_place = |parent: u64| {
+                    let index = usize::try_from(parent).ok()?.checked_sub(1)?;
+                    in_place.get(index).copied()
+                };
+                parent == old.parent
+                    && old
+                        .parent
+                        .is_none_or(|parent| parent_in_place(parent) == Some(true))
+            });
+            in_place.push(kept);
+        }
+        // the old tasks still in the file, in file order
+        let mut ids = Vec::new();
+        // from the end, so that the lines of the items not handled yet don't move. a task that
+        // moved to another parent is removed and added again below it, along with its subtasks
+        for ((item, old), kept) in items.iter().zip(&before).zip(in_place).rev() {
+            match tasks.iter().find(|t| t.id == old.id) {
+                Some(task) if kept => {
+                    if !same(task, old) {
+                        self.lines[item.line] = self.render(&item.prefix, task);
+                    }
+                    ids.push(old.id);
+                }
+                _ => {
+                    let end = self.block_end(item.line);
+                    self.lines.drain(item.line..end);
+                }
+            }
+        }
+        ids.reverse();
+
+        let added = tasks
+            .iter()
+            .filter(|t| !ids.contains(&t.id))
+            .collect::<Vec<_>>();
+        for task in added {
+            let items = self.items();
+            let parent = task
+                .parent
+                .and_then(|parent| ids.iter().position(|&id| id == parent));
+            let (line, prefix) = match parent {
+                Some(parent) => (
+                    self.subtree_end(&items, parent),
+                    self.child_prefix(&items, parent),
+                ),
+                None => (
+                    items
+                        .last()
+                        .map_or(self.lines.len(), |last| self.block_end(last.line)),
+                    self.top_prefix(&items),
+                ),
+            };
+            self.lines.insert(line, self.render(&prefix, task));
+            let position = items.iter().filter(|item| item.line < line).count();
+            ids.insert(position, task.id);
+        }
+    }
+
+    fn items(&self) -> Vec<Item> {
+        let mut items: Vec<Item> = Vec::new();
+        // the levels of the items and Org headlines a line can be nested in
+        let mut open: Vec<(usize, Option<usize>)> = Vec::new();
+        let mut fenced = false;
+        for (number, line) in self.lines.iter().enumerate() {
+            // Markdown code blocks are never tasks
+            if self.style == Style::Markdown && line.trim_start().starts_with("```") {
+                fenced = !fenced;
+                continue;
+            }
+            if fenced {
+                continue;
+            }
+            let Some(parsed) = self.parse_line(line) else {
+                continue;
+            };
+            while open.last().is_some_and(|(l, _)| *l >= parsed.level()) {
+                open.pop();
+            }
+            let parent = open.last().and_then(|(_, index)| *index);
+            let Line::Task {
+                level,
+                prefix,
+                done,
+                text,
+            } = parsed
+            else {
+                open.push((parsed.level(), None));
+                continue;
+            };
+            open.push((level, Some(items.len())));
+            items.push(Item {
+                line: number,
+                level,
+                parent,
+                prefix,
+                done,
+                text,
+            });
+        }
+        items
+    }
+
+    // the level of a task line or Org headline, and for a task, its prefix, status and text
+    fn parse_line(&self, line: &str) -> Option<Line> {
+        match self.style {
+            Style::Markdown => {
+                let trimmed = line.trim_start();
+                let indent = line.len() - trimmed.len();
+                let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+                let bullet = if trimmed.starts_with(['-', '*', '+']) {
+                    1
+                } else if digits > 0 && trimmed[digits..].starts_with(['.', ')']) {
+                    digits + 1
+                } else {
+                    return None;
+                };
+                let rest = trimmed[bullet..].strip_prefix(' ')?;
+                let done = match rest.get(..3)? {
+                    "[ ]" => false,
+                    "[x]" | "[X]" => true,
+                    _ => return None,
+                };
+                let text = &rest[3..];
+                if !text.is_empty() && !text.starts_with([' ', '\t']) || text.trim().is_empty() {
+                    return None;
+                }
+                let prefix = format!("{} ", &line[..indent + bullet]);
+                Some(Line::Task {
+                    level: indent,
+                    prefix,
+                    done,
+                    text: text.trim().to_string(),
+                })
+            }
+            Style::Org => {
+                let stars = line.chars().take_while(|c| *c == '*').count();
+                let rest = line[stars..].strip_prefix(' ')?;
+                if stars == 0 {
+                    return None;
+                }
+                let rest = rest.trim_start();
+                let (keyword, text) = rest.split_once(' ').unwrap_or((rest, ""));
+                let done = match keyword {
+                    "TODO" => false,
+                    "DONE" => true,
+                    _ => return Some(Line::Heading(stars)),
+                };
+                if text.trim().is_empty() {
+                    return Some(Line::Heading(stars));
+                }
+                let prefix = format!("{} ", "*".repeat(stars));
+                Some(Line::Task {
+                    level: stars,
+                    prefix,
+                    done,
+                    text: text.trim().to_string(),
+                })
+            }
+        }
+    }
+
+    // the title, priority, tags and project written in the text of a task line
+    fn parse_text(&self, text: &str) -> (String, Option<Priority>, Vec<String>, Option<String>) {
+        let (text, priority, org_tags) = match self.style {
+            // priority as `!`, `!!` or `!!!`, the same way they are typed in `pls add`
+            Style::Markdown => {
+                let (text, priority) = priority::strip_inline(text);
+                (text, priority, Vec::new())
+            }
+            // priority as a [#A] cookie, and tags at the end, ex: `TODO [#A] title :work:home:`
+            Style::Org => {
+                let (priority, text) = match text.get(..4) {
+                    Some("[#A]") => (Some(Priority::High), &text[4..]),
+                    Some("[#B]") => (Some(Priority::Medium), &text[4..]),
+                    Some("[#C]") => (Some(Priority::Low), &text[4..]),
+                    _ => (None, text),
+                };
+                let text = text.trim();
+                let tags = text
+                    .rsplit_once(char::is_whitespace)
+                    .map(|(_, last)| last)
+                    .filter(|last| last.len() > 2 && last.starts_with(':') && last.ends_with(':'));
+                let (text, tags) = tags.map_or((text, Vec::new()), |tags| {
+                    (
+                        text[..text.len() - tags.len()].trim(),
+                        tags.split(':')
+                            .filter(|tag| !tag.is_empty())
+                            .map(str::to_lowercase)
+                            .collect(),
+                    )
+                });
+                (text.to_string(), priority, tags)
+            }
+        };
+        let (title, mut tags, project) = filter::strip_tags(&text);
+        for tag in org_tags {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        (title, priority, tags, project)
+    }
+
+    // the line of a task, after the prefix of its item
+    fn render(&self, prefix: &str, task: &Task) -> String {
+        let mut words = Vec::new();
+        match self.style {
+            Style::Markdown => {
+                words.push(format!(
+                    "{prefix}[{}]",
+                    if task.completed { 'x' } else { ' ' }
+                ));
+                words.push(task.title.clone());
+                words.extend(task.tags.iter().map(|tag| format!("+{tag}")));
+                words.extend(task.project.iter().map(|project| format!("@{project}")));
+                words.extend(task.priority.map(|priority| {
+                    match priority {
+                        Priority::High => "!!!",
+                        Priority::Medium => "!!",
+                        Priority::Low => "!",
+                    }
+                    .to_string()
+                }));
+            }
+            Style::Org => {
+                words.push(format!(
+                    "{prefix}{}",
+                    if task.completed { "DONE" } else { "TODO" }
+                ));
+                words.extend(task.priority.map(|priority| {
+                    match priority {
+                        Priority::High => "[#A]",
+                        Priority::Medium => "[#B]",
+                        Priority::Low => "[#C]",
+                    }
+                    .to_string()
+                }));
+                words.push(task.title.clone());
+                words.extend(task.project.iter().map(|project| format!("@{project}")));
+                if !task.tags.is_empty() {
+                    words.push(format!(":{}:", task.tags.join(":")));
+                }
+            }
+        }
+        words.join(" ")
+    }
+
+    // the line after the last one that belongs to the task on `line`: in Org, the text up to
+    // the next headline is part of it
+    fn block_end(&self, line: usize) -> usize {
+        match self.style {
+            Style::Markdown => line + 1,
+            Style::Org => (line + 1..self.lines.len())
+                .find(|&l| self.parse_line(&self.lines[l]).is_some())
+                .unwrap_or(self.lines.len()),
+        }
+    }
+
+    // the line after the last subtask, at any depth, of an item
+    fn subtree_end(&self, items: &[Item], index: usize) -> usize {
+        let is_below = |mut item: usize| {
+            while let Some(parent) = items[item].parent {
+                if parent == index {
+                    return true;
+                }
+                item = parent;
+            }
+            false
+        };
+        let last = (index + 1..items.len())
+            .take_while(|&item| is_below(item))
+            .last()
+            .unwrap_or(index);
+        self.block_end(items[last].line)
+    }
+
+    // the prefix of a new subtask: the same as the item's other subtasks, or one level deeper
+    fn child_prefix(&self, items: &[Item], index: usize) -> String {
+        if let Some(sibling) = items.iter().find(|item| item.parent == Some(index)) {
+            return sibling.prefix.clone();
+        }
+        let parent = &items[index];
+        match self.style {
+            Style::Markdown => format!("{}  - ", " ".repeat(parent.level)),
+            Style::Org => format!("{} ", "*".repeat(parent.level + 1)),
+        }
+    }
+
+    // the prefix of a new task that isn't a subtask: the same as the last such task
+    fn top_prefix(&self, items: &[Item]) -> String {
+        let last = items.iter().rev().find(|item| item.parent.is_none());
+        match (self.style, last) {
+            // numbered lists don't get a number twice
+            (Style::Markdown, Some(last)) if last.prefix.trim().ends_with(['.', ')']) => {
+                format!("{}- ", " ".repeat(last.level))
+            }
+            (_, Some(last)) => last.prefix.clone(),
+            (Style::Markdown, None) => "- ".to_string(),
+            (Style::Org, None) => "* ".to_string(),
+        }
+    }
+}
+
+/// The fields of a task a checklist file has no place for, ex: \["due date"\].
+pub fn unsupported(task: &Task) -> Vec<&'static str> {
+    [
+        (task.due.is_some(), "due date"),
+        (task.recurrence.is_some(), "recurrence"),
+        (!task.notes.is_empty(), "notes"),
+    ]
+    .into_iter()
+    .filter_map(|(has, field)| has.then_some(field))
+    .collect()
+}
+
+// whether two tasks would be written the same way
+fn same(a: &Task, b: &Task) -> bool {
+    a.title == b.title
+        && a.completed == b.completed
+        && a.priority == b.priority
+        && a.tags == b.tags
+        && a.project == b.project
+}
@@ -0,0 +1,121 @@
+use yansi::Paint;
+
+use crate::store::Store;
+
+/// A setting that can be changed with `pls config`. Settings are stored as strings in the
+/// database under their key.
+pub struct Setting {
+    pub key: &'static str,
+    pub about: &'static str,
+    validate: fn(&str) -> Result<(), String>,
+    // whether the value is hidden when settings are shown, ex: a password
+    secret: bool,
+}
+
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "history-size",
+        about: "Number of operations kept for `pls revert` (default 50)",
+        validate: |value| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|size| *size > 0)
+                .map(|_| ())
+                .ok_or_else(|| "must be a number greater than 0".to_string())
+        },
+        secret: false,
+    },
+    Setting {
+        key: "caldav-url",
+        about: "URL of the CalDAV collection `pls sync caldav` syncs with",
+        validate: |value| {
+            if value.starts_with("http://") || value.starts_with("https://") {
+                Ok(())
+            } else {
+                Err("must start with http:// or https://".to_string())
+            }
+        },
+        secret: false,
+    },
+    Setting {
+        key: "caldav-user",
+        about: "User name to log in to the CalDAV server with",
+        validate: |_| Ok(()),
+        secret: false,
+    },
+    Setting {
+        key: "caldav-password",
+        about: "Password to log in to the CalDAV server with, PLS_CALDAV_PASSWORD takes precedence",
+        validate: |_| Ok(()),
+        secret: true,
+    },
+    Setting {
+        key: "caldav-allow-http",
+        about: "Whether `pls sync caldav` can send the password unencrypted to an http:// URL (default false)",
+        validate: |value| {
+            value
+                .parse::<bool>()
+                .map(drop)
+                .map_err(|_| "must be true or false".to_string())
+        },
+        secret: false,
+    },
+];
+
+/// Show every setting, show one setting, or change one setting.
+pub fn config(db: &mut dyn Store, key: Option<&String>, value: Option<&String>) {
+    let Some(key) = key else {
+        for setting in SETTINGS {
+            println!(
+                "{} = {} {}",
+                Paint::yellow(setting.key),
+                shown(setting, db.setting(setting.key)),
+                Paint::blue(&format!("# {}", setting.about))
+            );
+        }
+        return;
+    };
+    let Some(setting) = SETTINGS.iter().find(|s| s.key == key) else {
+        println!(
+            "{}",
+            Paint::red(&format!(
+                "Error: unknown setting \"{key}\", run `pls config` to see all settings"
+            ))
+        );
+        return;
+    };
+    match value {
+        None => println!("{}", shown(setting, db.setting(key))),
+        // an empty value resets the setting to its default
+        Some(value) if value.is_empty() => {
+            db.rem(key).expect("Failed to remove setting");
+        }
+        Some(value) => match set(db, key, value) {
+            Ok(()) => println!(
+                "Set {} to {}",
+                Paint::yellow(key),
+                Paint::green(&shown(setting, Some(value.clone())))
+            ),
+            Err(e) => println!("{}", Paint::red(&format!("Error: {e}"))),
+        },
+    }
+}
+
+/// Change a setting, if the value is valid for it.
+pub fn set(db: &mut dyn Store, key: &str, value: &str) -> Result<(), String> {
+    let setting = SETTINGS
+        .iter()
+        .find(|s| s.key == key)
+        .ok_or_else(|| format!("unknown setting \"{key}\""))?;
+    (setting.validate)(value).map_err(|e| format!("{key} {e}"))?;
+    db.set(key, value)
+}
+
+// the value of a setting as it is shown, hidden if it is a secret
+fn shown(setting: &Setting, value: Option<String>) -> String {
+    match value {
+        Some(_) if setting.secret => "********".to_string(),
+        value => value.unwrap_or_default(),
+    }
+}
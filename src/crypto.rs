@@ -0,0 +1,314 @@
+use std::{
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+
+// what an encrypted file starts with, followed by the salt, the nonce and the ciphertext
+const MAGIC: &[u8] = b"pls-encrypted-v1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// The variable a key can be given in, as printed by `pls unlock`, so that the passphrase
+/// doesn't have to be typed in.
+pub const KEY_VAR: &str = "PLS_KEY";
+
+// how long the agent keeps a key for, unless PLS_KEY_MINUTES says otherwise (0 not to keep it)
+const AGENT_MINUTES: u64 = 15;
+
+/// A key derived from a passphrase, along with the salt it was derived with. The salt is kept
+/// in the encrypted file, so the key stays the same every time the file is written.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    salt: [u8; SALT_LEN],
+    key: [u8; 32],
+}
+
+impl Key {
+    /// Derive a key from a passphrase with a new salt, to encrypt a file with.
+    pub fn generate(passphrase: &str) -> Result<Self, String> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self::derive(passphrase, salt)
+    }
+
+    // argon2id with its default cost, which takes a moment on purpose
+    fn derive(passphrase: &str, salt: [u8; SALT_LEN]) -> Result<Self, String> {
+        let mut key = [0; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Failed to derive a key: {e}"))?;
+        Ok(Self { salt, key })
+    }
+
+    /// The key as hex, the salt first, as given in `PLS_KEY`.
+    pub fn to_hex(&self) -> String {
+        self.salt
+            .iter()
+            .chain(&self.key)
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    fn from_hex(hex: &str) -> Option<Self> {
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        let (salt, key) = bytes.split_at_checked(SALT_LEN)?;
+        Some(Self {
+            salt: salt.try_into().ok()?,
+            key: key.try_into().ok()?,
+        })
+    }
+}
+
+/// Whether the contents of a file were written by `encrypt`.
+pub fn is_encrypted(contents: &[u8]) -> bool {
+    contents.starts_with(MAGIC)
+}
+
+/// Encrypt contents with XChaCha20-Poly1305, which also makes sure they aren't tampered with.
+pub fn encrypt(key: &Key, contents: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&key.key));
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&key.salt);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: contents,
+                aad: &header,
+            },
+        )
+        .map_err(|_| "Failed to encrypt".to_string())?;
+    header.extend_from_slice(&nonce);
+    header.extend(ciphertext);
+    Ok(header)
+}
+
+/// Decrypt what `encrypt` wrote, which fails if the key is wrong or the file was changed.
+pub fn decrypt(key: &Key, encrypted: &[u8]) -> Result<Vec<u8>, String> {
+    let header_len = MAGIC.len() + SALT_LEN;
+    let wrong = || "wrong passphrase, or the file is damaged".to_string();
+    if !is_encrypted(encrypted) || encrypted.len() < header_len + NONCE_LEN {
+        return Err(wrong());
+    }
+    let (header, rest) = encrypted.split_at(header_len);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    if header[MAGIC.len()..] != key.salt {
+        return Err(wrong());
+    }
+    XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&key.key))
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| wrong())
+}
+
+/// Find the key of the encrypted file at `path` and decrypt its contents. The key is taken from
+/// `PLS_KEY`, or from the agent of the file, or else derived from a passphrase typed in, and
+/// then, if `keep` is set, kept by a new agent for a while so that the next pls commands start
+/// as fast as ever.
+pub fn unlock(path: &Path, encrypted: &[u8], keep: bool) -> Result<(Key, Vec<u8>), String> {
+    let cached = std::env::var(KEY_VAR)
+        .ok()
+        .into_iter()
+        .chain(agent_request(&socket(path), "key"))
+        .filter_map(|hex| Key::from_hex(hex.trim()));
+    for key in cached {
+        if let Ok(contents) = decrypt(&key, encrypted) {
+            return Ok((key, contents));
+        }
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(format!(
+            "{} is encrypted, set {KEY_VAR} or run pls in a terminal to type the passphrase",
+            path.display()
+        ));
+    }
+    let salt = encrypted
+        .get(MAGIC.len()..MAGIC.len() + SALT_LEN)
+        .and_then(|salt| salt.try_into().ok())
+        .ok_or_else(|| "the encrypted file is damaged".to_string())?;
+    let key = Key::derive(&read_passphrase("Passphrase: ")?, salt)?;
+    let contents = decrypt(&key, encrypted)?;
+    if keep {
+        start_agent(&key, path);
+    }
+    Ok((key, contents))
+}
+
+/// Ask for a new passphrase twice, to make sure it was typed in as meant.
+pub fn new_passphrase() -> Result<String, String> {
+    let passphrase = read_passphrase("New passphrase: ")?;
+    if read_passphrase("Repeat the passphrase: ")? != passphrase {
+        return Err("the passphrases don't match".to_string());
+    }
+    Ok(passphrase)
+}
+
+// read a passphrase from the terminal without showing it. the prompt goes to stderr, so that
+// `eval "$(pls unlock)"` only gets the key
+fn read_passphrase(prompt: &str) -> Result<String, String> {
+    eprint!("{prompt}");
+    drop(std::io::stderr().flush());
+    let hidden = Command::new("stty")
+        .arg("-echo")
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    let mut passphrase = String::new();
+    let read = std::io::stdin().read_line(&mut passphrase);
+    if hidden {
+        drop(Command::new("stty").arg("echo").status());
+        eprintln!();
+    }
+    read.map_err(|e| format!("Failed to read the passphrase: {e}"))?;
+    let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+    if passphrase.is_empty() {
+        return Err("no passphrase given".to_string());
+    }
+    Ok(passphrase.to_string())
+}
+
+/// Start an agent that keeps the key of the file at `path` in memory, and gives it to the pls
+/// processes that ask for it, for as long as `PLS_KEY_MINUTES` says.
+pub fn start_agent(key: &Key, path: &Path) {
+    if agent_minutes() == 0 || !cfg!(unix) {
+        return;
+    }
+    // an agent already running for another key is replaced
+    lock(path);
+    let Ok(exe) = std::env::current_exe() else {
+        return;
+    };
+    let mut command = Command::new(exe);
+    command
+        .arg("agent")
+        .arg(socket(path))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // in a process group of its own, so that Ctrl-C in the terminal doesn't stop it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let child = command.spawn();
+    // the key is given on stdin, where other users can't see it
+    if let Some(mut stdin) = child.ok().and_then(|mut child| child.stdin.take()) {
+        drop(writeln!(stdin, "{}", key.to_hex()));
+    }
+}
+
+/// Stop the agent of the file at `path`, forgetting the key it kept. Returns whether one was
+/// running.
+pub fn lock(path: &Path) -> bool {
+    agent_request(&socket(path), "lock").is_some()
+}
+
+// where the agent of a file listens, ex: pls.sock for pls.json
+fn socket(path: &Path) -> PathBuf {
+    path.with_extension("sock")
+}
+
+fn agent_minutes() -> u64 {
+    std::env::var("PLS_KEY_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(AGENT_MINUTES)
+}
+
+/// Run the agent: keep the key read from stdin and give it to whoever asks for it on the
+/// socket, which only the user can connect to, until the time is up or `pls lock` is run.
+#[cfg(unix)]
+pub fn agent(socket: &Path) -> Result<(), String> {
+    use std::{
+        io::{BufRead, BufReader},
+        os::unix::{
+            fs::{DirBuilderExt, PermissionsExt},
+            net::UnixListener,
+        },
+        time::Duration,
+    };
+
+    let mut key = String::new();
+    std::io::stdin()
+        .read_line(&mut key)
+        .map_err(|e| format!("Failed to read the key: {e}"))?;
+    // bound in a directory only the user can get into, and moved into place once it's protected,
+    // so that no one else can connect to it in between
+    let dir = socket.with_extension(format!("sock.{}", std::process::id()));
+    drop(std::fs::remove_dir_all(&dir));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    let bound = dir.join("sock");
+    let opened = UnixListener::bind(&bound)
+        .map_err(|e| format!("Failed to listen on {}: {e}", socket.display()))
+        .and_then(|listener| {
+            std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))
+                .and_then(|()| std::fs::rename(&bound, socket))
+                .map(|()| listener)
+                .map_err(|e| format!("Failed to protect {}: {e}", socket.display()))
+        });
+    drop(std::fs::remove_dir_all(&dir));
+    let listener = opened?;
+    let path = socket.to_path_buf();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_secs(agent_minutes().saturating_mul(60)));
+        drop(std::fs::remove_file(&path));
+        std::process::exit(0);
+    });
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        let mut request = String::new();
+        drop(BufReader::new(&stream).read_line(&mut request));
+        match request.trim() {
+            "key" => drop(stream.write_all(key.as_bytes())),
+            "lock" => {
+                drop(stream.write_all(b"ok\n"));
+                break;
+            }
+            _ => {}
+        }
+    }
+    drop(std::fs::remove_file(socket));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn agent(_socket: &Path) -> Result<(), String> {
+    Err("the agent only runs on unix".to_string())
+}
+
+// send a request to the agent, returning its answer, or none if no agent is running
+#[cfg(unix)]
+fn agent_request(socket: &Path, request: &str) -> Option<String> {
+    use std::{io::Read, os::unix::net::UnixStream};
+
+    let mut stream = UnixStream::connect(socket).ok()?;
+    writeln!(stream, "{request}").ok()?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).ok()?;
+    Some(answer)
+}
+
+#[cfg(not(unix))]
+fn agent_request(_socket: &Path, _request: &str) -> Option<String> {
+    None
+}
@@ -0,0 +1,176 @@
+use time::{
+    macros::{format_description, time},
+    Date, Duration, Month, OffsetDateTime, Time, Weekday,
+};
+use yansi::Paint;
+
+// time used when a due date only names a day, so that a task due "today" isn't
+// already overdue the moment it is added
+pub const END_OF_DAY: Time = time!(23:59);
+
+/// Parse a due date such as "tomorrow", "in 3 days", "next friday 5pm" or
+/// "2024-05-17 09:30", relative to `now`. Returns a unix timestamp.
+pub fn parse_due(input: &str, now: OffsetDateTime) -> Result<i64, String> {
+    let input = input.trim().to_lowercase();
+    let words = input
+        .split_whitespace()
+        .filter(|w| *w != "at" && *w != "on")
+        .collect::<Vec<_>>();
+    let error = || format!("Could not understand due date \"{input}\"");
+
+    let mut date: Option<Date> = None;
+    let mut clock: Option<Time> = None;
+    let mut i = 0;
+    while i < words.len() {
+        let word = words[i];
+        match word {
+            "today" | "tonight" => date = Some(now.date()),
+            "tomorrow" | "tmr" => date = Some(now.date() + Duration::days(1)),
+            "in" => {
+                // "in 3 days", "in 2 hours"
+                let amount = words
+                    .get(i + 1)
+                    .and_then(|n| n.parse::<i64>().ok())
+                    .ok_or_else(error)?;
+                let unit = words.get(i + 2).ok_or_else(error)?;
+                let seconds = match unit.trim_end_matches('s') {
+                    "min" | "minute" => 60,
+                    "hour" | "hr" | "h" => 3600,
+                    "day" | "d" => 86400,
+                    "week" | "wk" | "w" => 604_800,
+                    _ => return Err(error()),
+                };
+                // too far off to be a date is an error too
+                let duration = amount
+                    .checked_mul(seconds)
+                    .map(Duration::seconds)
+                    .ok_or_else(error)?;
+                let due = now.checked_add(duration).ok_or_else(error)?;
+                // relative offsets smaller than a day are exact, larger ones are
+                // rounded to the end of the day unless a time is given
+                if duration < Duration::days(1) {
+                    clock = Some(due.time());
+                }
+                date = Some(due.date());
+                i += 2;
+            }
+            "next" | "this" => {
+                let next = words.get(i + 1).ok_or_else(error)?;
+                date = Some(if *next == "week" {
+                    now.date() + Duration::weeks(1)
+                } else {
+                    now.date()
+                        .next_occurrence(parse_weekday(next).ok_or_else(error)?)
+                });
+                i += 1;
+            }
+            _ => {
+                if let Some(weekday) = parse_weekday(word) {
+                    date = Some(now.date().next_occurrence(weekday));
+                } else if let Some(day) = parse_iso_date(word) {
+                    date = Some(day);
+                } else if let Some(time) = parse_clock(word) {
+                    clock = Some(time);
+                } else if let Some((day, time)) = word.split_once('t') {
+                    // ISO 8601 such as 2024-05-17t09:30 (input is lowercased)
+                    date = Some(parse_iso_date(day).ok_or_else(error)?);
+                    clock = Some(parse_clock(time).ok_or_else(error)?);
+                } else {
+                    return Err(error());
+                }
+            }
+        }
+        i += 1;
+    }
+
+    let due = match (date, clock) {
+        (Some(date), clock) => date.with_time(clock.unwrap_or(END_OF_DAY)),
+        // a bare time means the next time the clock shows it
+        (None, Some(clock)) if clock > now.time() => now.date().with_time(clock),
+        (None, Some(clock)) => (now.date() + Duration::days(1)).with_time(clock),
+        (None, None) => return Err(error()),
+    };
+    Ok(due.assume_offset(now.offset()).unix_timestamp())
+}
+
+/// Render a due date for the task table: red when overdue, yellow when due today.
+pub fn format_due(due: i64, completed: bool, now: OffsetDateTime) -> String {
+    let Ok(due) = OffsetDateTime::from_unix_timestamp(due) else {
+        return String::new();
+    };
+    let due = due.to_offset(now.offset());
+    let format =
+        format_description!("[weekday repr:short], [day] [month repr:short] [hour]:[minute]");
+    let text = due.format(&format).unwrap_or_else(|_| due.to_string());
+    if completed {
+        text
+    } else if due < now {
+        Paint::red(&format!("{text} (overdue)")).to_string()
+    } else if due.date() == now.date() {
+        Paint::yellow(&text).to_string()
+    } else {
+        text
+    }
+}
+
+/// Render how long ago a timestamp was, ex: "3d ago".
+pub fn format_age(timestamp: i64, now: OffsetDateTime) -> String {
+    let seconds = now.unix_timestamp() - timestamp;
+    match seconds {
+        i64::MIN..=59 => "just now".to_string(),
+        60..=3_599 => format!("{}m ago", seconds / 60),
+        3_600..=86_399 => format!("{}h ago", seconds / 3_600),
+        86_400..=2_591_999 => format!("{}d ago", seconds / 86_400),
+        2_592_000..=31_535_999 => format!("{}mo ago", seconds / 2_592_000),
+        _ => format!("{}y ago", seconds / 31_536_000),
+    }
+}
+
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    Some(match word {
+        "monday" | "mon" => Weekday::Monday,
+        "tuesday" | "tue" | "tues" => Weekday::Tuesday,
+        "wednesday" | "wed" => Weekday::Wednesday,
+        "thursday" | "thu" | "thurs" => Weekday::Thursday,
+        "friday" | "fri" => Weekday::Friday,
+        "saturday" | "sat" => Weekday::Saturday,
+        "sunday" | "sun" => Weekday::Sunday,
+        _ => return None,
+    })
+}
+
+/// Parse a date such as "2024-05-17".
+pub fn parse_iso_date(word: &str) -> Option<Date> {
+    let mut parts = word.splitn(3, '-');
+    let year = parts.next()?.parse::<i32>().ok()?;
+    let month = Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse::<u8>().ok()?;
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+// accepts "17:00", "5pm", "5:30pm", "noon" and "midnight"
+fn parse_clock(word: &str) -> Option<Time> {
+    match word {
+        "noon" => return Some(time!(12:00)),
+        "midnight" => return Some(time!(0:00)),
+        _ => {}
+    }
+    let (digits, pm) = match (word.strip_suffix("pm"), word.strip_suffix("am")) {
+        (Some(digits), _) => (digits, Some(true)),
+        (None, Some(digits)) => (digits, Some(false)),
+        (None, None) => (word, None),
+    };
+    let (hour, minute) = match digits.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u8>().ok()?, minute.parse::<u8>().ok()?),
+        // a bare number is only a time with am/pm, otherwise it's ambiguous
+        None if pm.is_some() => (digits.parse::<u8>().ok()?, 0),
+        None => return None,
+    };
+    let hour = match pm {
+        Some(true) if hour < 12 => hour + 12,
+        Some(false) if hour == 12 => 0,
+        Some(_) if hour > 12 => return None,
+        _ => hour,
+    };
+    Time::from_hms(hour, minute, 0).ok()
+}
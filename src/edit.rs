@@ -0,0 +1,147 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    process::Command,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{due::parse_due, get_time, priority::Priority, recur::Recurrence, Task};
+
+// the fields of a task that can be changed in an editor. the id is left out on purpose so that
+// an edit can never make two tasks share an id
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EditableTask {
+    title: String,
+    completed: bool,
+    due: Option<String>,
+    priority: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    project: Option<String>,
+    every: Option<String>,
+}
+
+const HEADER: &str = "\
+# Edit the task below, then save and close the editor to apply the changes.
+# due accepts anything `pls add --due` does, priority is high, med or low.
+# every is daily, weekday, weekly:<day> or monthly:<day>, for recurring tasks.
+# Optional fields (due, priority, project, every) can be removed to clear them.
+";
+
+/// Open the task in `$VISUAL`/`$EDITOR` as TOML and return the edited task.
+pub fn edit_in_editor(task: &Task) -> Result<Task, String> {
+    let editable = EditableTask {
+        title: task.title.clone(),
+        completed: task.completed,
+        due: task.due.and_then(|due| format_due_input(due, get_time())),
+        priority: task.priority.map(|p| p.to_string()),
+        tags: task.tags.clone(),
+        project: task.project.clone(),
+        every: task.recurrence.map(|r| r.to_string()),
+    };
+    let contents =
+        toml::to_string(&editable).map_err(|e| format!("Failed to serialize task: {e}"))?;
+
+    let (path, mut file) = create_temp_file()?;
+    file.write_all(format!("{HEADER}{contents}").as_bytes())
+        .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+    drop(file);
+
+    // editors are often configured with arguments, such as "code --wait"
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let status = Command::new(words.next().unwrap_or("vi"))
+        .args(words)
+        .arg(&path)
+        .status()
+        .map_err(|e| format!("Failed to launch editor \"{editor}\": {e}"));
+    let edited =
+        fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {e}", path.display()));
+    drop(fs::remove_file(&path));
+    if !status?.success() {
+        return Err("Editor exited with an error, task left unchanged".to_string());
+    }
+
+    apply_edit(task, &edited?)
+}
+
+// create a file in the temp dir that no other user can read. the name is only unique, not
+// secret: create_new never opens a file or follows a symlink someone else left under it, and
+// two edits at once never share a file
+fn create_temp_file() -> Result<(PathBuf, File), String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut attempts = 0;
+    loop {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let name = format!("pls-edit-{}-{nanos:x}.toml", std::process::id());
+        let path = env::temp_dir().join(name);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists && attempts < 100 => attempts += 1,
+            Err(e) => return Err(format!("Failed to create {}: {e}", path.display())),
+        }
+    }
+}
+
+/// Validate edited TOML and apply it to a copy of the task.
+pub fn apply_edit(task: &Task, edited: &str) -> Result<Task, String> {
+    let edited =
+        toml::from_str::<EditableTask>(edited).map_err(|e| format!("Invalid task: {e}"))?;
+    let title = edited.title.trim();
+    if title.is_empty() {
+        return Err("Invalid task: title can't be empty".to_string());
+    }
+    // keep the completion time in step with the completed flag
+    let completed_at = match (task.completed, edited.completed) {
+        (false, true) => Some(get_time().unix_timestamp()),
+        (_, false) => None,
+        (true, true) => task.completed_at,
+    };
+    Ok(Task {
+        title: title.to_string(),
+        completed: edited.completed,
+        completed_at,
+        due: edited
+            .due
+            .map(|due| parse_due(&due, get_time()))
+            .transpose()?,
+        priority: edited.priority.map(|p| p.parse::<Priority>()).transpose()?,
+        tags: edited
+            .tags
+            .iter()
+            .map(|tag| tag.trim_start_matches('+').to_lowercase())
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+        project: edited
+            .project
+            .map(|p| p.trim_start_matches('@').to_lowercase())
+            .filter(|p| !p.is_empty()),
+        recurrence: edited
+            .every
+            .map(|every| every.parse::<Recurrence>())
+            .transpose()?,
+        ..task.clone()
+    })
+}
+
+/// Format a time so that it can be parsed back by `parse_due`, ex: "2024-05-17 09:30".
+pub fn format_due_input(due: i64, now: OffsetDateTime) -> Option<String> {
+    OffsetDateTime::from_unix_timestamp(due)
+        .ok()?
+        .to_offset(now.offset())
+        .format(format_description!("[year]-[month]-[day] [hour]:[minute]"))
+        .ok()
+}
@@ -0,0 +1,82 @@
+use yansi::Paint;
+
+use crate::Task;
+
+/// `+tag` and `@project` tokens used to narrow down the task list.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct Filter {
+    tags: Vec<String>,
+    projects: Vec<String>,
+}
+
+impl Filter {
+    pub fn parse<'a>(args: impl IntoIterator<Item = &'a String>) -> Result<Self, String> {
+        let mut filter = Self::default();
+        for arg in args {
+            if let Some(tag) = arg.strip_prefix('+').filter(|t| !t.is_empty()) {
+                filter.tags.push(tag.to_lowercase());
+            } else if let Some(project) = arg.strip_prefix('@').filter(|p| !p.is_empty()) {
+                filter.projects.push(project.to_lowercase());
+            } else {
+                return Err(format!(
+                    "Unknown filter \"{arg}\", must be a +tag or an @project"
+                ));
+            }
+        }
+        Ok(filter)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.projects.is_empty()
+    }
+
+    /// A task matches when it has every tag and belongs to one of the projects.
+    pub fn matches(&self, task: &Task) -> bool {
+        self.tags.iter().all(|tag| task.tags.contains(tag))
+            && (self.projects.is_empty()
+                || task
+                    .project
+                    .as_ref()
+                    .is_some_and(|p| self.projects.contains(p)))
+    }
+}
+
+/// Split `+tag` and `@project` tokens out of a task title.
+pub fn strip_tags(title: &str) -> (String, Vec<String>, Option<String>) {
+    let mut tags = Vec::new();
+    let mut project = None;
+    let words = title
+        .split_whitespace()
+        .filter(
+            |word| match (word.strip_prefix('+'), word.strip_prefix('@')) {
+                (Some(tag), _) if !tag.is_empty() => {
+                    let tag = tag.to_lowercase();
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                    false
+                }
+                (_, Some(p)) if !p.is_empty() => {
+                    project = Some(p.to_lowercase());
+                    false
+                }
+                _ => true,
+            },
+        )
+        .collect::<Vec<_>>();
+    (words.join(" "), tags, project)
+}
+
+/// Tags and project of a task, formatted the way they are typed.
+pub fn paint_tags(task: &Task) -> String {
+    task.tags
+        .iter()
+        .map(|tag| Paint::cyan(&format!("+{tag}")).to_string())
+        .chain(
+            task.project
+                .iter()
+                .map(|project| Paint::magenta(&format!("@{project}")).to_string()),
+        )
+        .collect::<Vec<_>>()
+        .join(" ")
+}
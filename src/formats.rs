@@ -0,0 +1,446 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    due::parse_due, edit::format_due_input, get_time, ical, priority::Priority, recur::Recurrence,
+    taskwarrior, todotxt, tree_tasks, Task,
+};
+
+/// Version of the JSON export. It only changes when a field is removed or changes meaning, so
+/// tools reading the export can rely on it.
+pub const JSON_VERSION: u32 = 1;
+
+/// A format tasks can be exported to, and for all but Markdown, imported from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    TodoTxt,
+    Json,
+    Csv,
+    Markdown,
+    Ics,
+    // `task export` and `task import`, which have no extension of their own
+    Taskwarrior,
+}
+
+impl Format {
+    /// Guess the format of a file from its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "txt" => Some(Self::TodoTxt),
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "md" | "markdown" => Some(Self::Markdown),
+            "ics" | "ical" => Some(Self::Ics),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "todotxt" | "todo.txt" => Ok(Self::TodoTxt),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "markdown" | "md" => Ok(Self::Markdown),
+            "ics" | "ical" | "icalendar" => Ok(Self::Ics),
+            "taskwarrior" | "task" => Ok(Self::Taskwarrior),
+            _ => Err(format!(
+                "Unknown format \"{s}\", must be todotxt, json, csv, markdown, ics or taskwarrior"
+            )),
+        }
+    }
+}
+
+pub fn export(tasks: &[Task], format: Format, now: OffsetDateTime) -> Result<String, String> {
+    match format {
+        Format::TodoTxt => Ok(todotxt::export(tasks, now.offset())),
+        Format::Json => {
+            let export = JsonExport {
+                version: JSON_VERSION,
+                tasks: tasks.iter().map(JsonTask::from).collect(),
+            };
+            serde_json::to_string_pretty(&export)
+                .map(|json| json + "\n")
+                .map_err(|e| format!("Failed to export tasks: {e}"))
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for task in tasks {
+                writer
+                    .serialize(CsvTask::new(task, now))
+                    .map_err(|e| format!("Failed to export tasks: {e}"))?;
+            }
+            let csv = writer
+                .into_inner()
+                .map_err(|e| format!("Failed to export tasks: {e}"))?;
+            String::from_utf8(csv).map_err(|e| format!("Failed to export tasks: {e}"))
+        }
+        Format::Markdown => Ok(markdown(tasks, now)),
+        Format::Ics => Ok(ical::export(tasks, now)),
+        Format::Taskwarrior => taskwarrior::export(tasks, now),
+    }
+}
+
+/// Tasks read from a file, and the fields in it that pls has no place for, with how many tasks
+/// had each.
+pub struct Imported {
+    pub tasks: Vec<Task>,
+    pub unmapped: BTreeMap<String, usize>,
+}
+
+impl From<Vec<Task>> for Imported {
+    fn from(tasks: Vec<Task>) -> Self {
+        Self {
+            tasks,
+            unmapped: BTreeMap::new(),
+        }
+    }
+}
+
+/// Read tasks from a file's contents. Ids and parents are the ones from the file, and are
+/// replaced by `add_imported`.
+pub fn import(text: &str, format: Format, now: OffsetDateTime) -> Result<Imported, String> {
+    let tasks = match format {
+        Format::TodoTxt => todotxt::import(text, now.offset()),
+        Format::Json => {
+            let export = serde_json::from_str::<JsonExport>(text).map_err(|e| {
+                // `task export` is a list of tasks
+                if text.trim_start().starts_with('[') {
+                    format!("Invalid JSON export: {e}, use --format taskwarrior for Taskwarrior")
+                } else {
+                    format!("Invalid JSON export: {e}")
+                }
+            })?;
+            if export.version > JSON_VERSION {
+                return Err(format!(
+                    "the file is version {} of the JSON export, but this version of pls only \
+                     understands up to {JSON_VERSION}",
+                    export.version
+                ));
+            }
+            export.tasks.into_iter().map(Task::from).collect()
+        }
+        Format::Csv => csv::Reader::from_reader(text.as_bytes())
+            .deserialize::<CsvTask>()
+            .enumerate()
+            .map(|(row, task)| {
+                // the header is line 1
+                let error = |e: String| format!("Invalid CSV on line {}: {e}", row + 2);
+                task.map_err(|e| error(e.to_string()))?
+                    .into_task(now)
+                    .map_err(error)
+            })
+            .collect::<Result<_, _>>()?,
+        Format::Markdown => return Err("Markdown can only be exported, not imported".to_string()),
+        Format::Ics => ical::import(text, now.offset())?,
+        Format::Taskwarrior => {
+            let (tasks, unmapped) = taskwarrior::import(text, now.offset())?;
+            return Ok(Imported { tasks, unmapped });
+        }
+    };
+    Ok(tasks.into())
+}
+
+/// How many imported tasks `add_imported` added, updated and skipped.
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct ImportCounts {
+    pub added: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+/// Add imported tasks to the list, giving them new ids and linking subtasks to their new
+/// parents. A task with the calendar UID or Taskwarrior UUID of a task in the list updates that
+/// task. Otherwise, tasks with the same title, project and due date as one already in the list
+/// (or earlier in the import) are skipped.
+pub fn add_imported(
+    tasks: &mut Vec<Task>,
+    imported: Vec<Task>,
+    mut next_id: impl FnMut() -> u64,
+) -> ImportCounts {
+    let mut counts = ImportCounts::default();
+    // ids in the file to ids in the list
+    let mut ids = HashMap::new();
+    let mut parents = Vec::new();
+    for mut task in imported {
+        let same_uid = task.uid.as_ref().and_then(|uid| {
+            tasks
+                .iter()
+                .position(|t| ical::uid(t) == *uid || taskwarrior::uuid(t) == *uid)
+        });
+        if let Some(index) = same_uid {
+            let existing = &mut tasks[index];
+            ids.insert(task.id, existing.id);
+            parents.push((existing.id, task.parent.take()));
+            // fields the file doesn't have, such as a project in a calendar from another app,
+            // stay as they are here
+            let updated = Task {
+                id: existing.id,
+                parent: existing.parent,
+                extra: existing.extra.clone(),
+                updated_at: existing.updated_at,
+                list: existing.list.clone(),
+                origin: existing.origin.clone(),
+                project: task.project.or_else(|| existing.project.clone()),
+                tags: or_existing(task.tags, &existing.tags),
+                recurrence: task.recurrence.or(existing.recurrence),
+                created_at: task.created_at.or(existing.created_at),
+                completed_at: task
+                    .completed_at
+                    .or_else(|| existing.completed_at.filter(|_| task.completed)),
+                // calendars have no notes, so only Taskwarrior replaces them
+                notes: or_existing(task.notes, &existing.notes),
+                ..task
+            };
+            if updated != *existing {
+                *existing = Task {
+                    updated_at: Some(get_time().unix_timestamp()),
+                    ..updated
+                };
+                counts.updated += 1;
+            }
+            continue;
+        }
+        if let Some(existing) = tasks.iter().find(|t| is_duplicate(t, &task)) {
+            if task.id != 0 {
+                ids.insert(task.id, existing.id);
+            }
+            counts.skipped += 1;
+            continue;
+        }
+        let id = next_id();
+        if task.id != 0 {
+            ids.insert(task.id, id);
+        }
+        task.id = id;
+        parents.push((id, task.parent.take()));
+        tasks.push(task);
+        counts.added += 1;
+    }
+    for (id, parent) in parents {
+        let parent = parent.and_then(|parent| ids.get(&parent).copied());
+        if let Some(task) = tasks.iter_mut().find(|t| t.id == id) {
+            task.parent = parent;
+        }
+    }
+    counts
+}
+
+// a list the imported file left empty keeps what the task had
+fn or_existing<T: Clone>(imported: Vec<T>, existing: &[T]) -> Vec<T> {
+    if imported.is_empty() {
+        existing.to_vec()
+    } else {
+        imported
+    }
+}
+
+// an empty CSV field is a missing value
+fn optional(text: &str) -> Option<&str> {
+    Some(text.trim()).filter(|t| !t.is_empty())
+}
+
+fn is_duplicate(a: &Task, b: &Task) -> bool {
+    a.title.trim().eq_ignore_ascii_case(b.title.trim()) && a.project == b.project && a.due == b.due
+}
+
+// a Markdown checklist, with subtasks indented below their parents
+fn markdown(tasks: &[Task], now: OffsetDateTime) -> String {
+    let mut text = String::new();
+    for (depth, task) in tree_tasks(tasks) {
+        let mut words = vec![format!(
+            "{}- [{}] {}",
+            "  ".repeat(depth),
+            if task.completed { 'x' } else { ' ' },
+            task.title
+        )];
+        words.extend(task.tags.iter().map(|tag| format!("+{tag}")));
+        words.extend(task.project.iter().map(|project| format!("@{project}")));
+        let details = task
+            .priority
+            .map(|p| format!("{p} priority"))
+            .into_iter()
+            .chain(
+                task.due
+                    .and_then(|due| format_due_input(due, now))
+                    .map(|due| format!("due {due}")),
+            )
+            .collect::<Vec<_>>();
+        if !details.is_empty() {
+            words.push(format!("({})", details.join(", ")));
+        }
+        text.push_str(&words.join(" "));
+        text.push('\n');
+    }
+    text
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonExport {
+    version: u32,
+    tasks: Vec<JsonTask>,
+}
+
+// the fields of a task in the JSON export, kept apart from `Task` so that the export doesn't
+// change when the way pls stores tasks does. times are unix timestamps
+#[derive(Serialize, Deserialize)]
+struct JsonTask {
+    id: u64,
+    title: String,
+    #[serde(default)]
+    completed: bool,
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[serde(default)]
+    due: Option<i64>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    project: Option<String>,
+    #[serde(default)]
+    recurrence: Option<Recurrence>,
+    #[serde(default)]
+    parent: Option<u64>,
+    #[serde(default)]
+    created_at: Option<i64>,
+    #[serde(default)]
+    updated_at: Option<i64>,
+    #[serde(default)]
+    completed_at: Option<i64>,
+}
+
+impl From<&Task> for JsonTask {
+    fn from(task: &Task) -> Self {
+        Self {
+            id: task.id,
+            title: task.title.clone(),
+            completed: task.completed,
+            priority: task.priority,
+            due: task.due,
+            tags: task.tags.clone(),
+            project: task.project.clone(),
+            recurrence: task.recurrence,
+            parent: task.parent,
+            created_at: task.created_at,
+            updated_at: task.updated_at,
+            completed_at: task.completed_at,
+        }
+    }
+}
+
+impl From<JsonTask> for Task {
+    fn from(task: JsonTask) -> Self {
+        Self {
+            id: task.id,
+            title: task.title,
+            completed: task.completed,
+            priority: task.priority,
+            due: task.due,
+            tags: task.tags,
+            project: task.project,
+            recurrence: task.recurrence,
+            parent: task.parent,
+            created_at: task.created_at,
+            updated_at: task.updated_at,
+            completed_at: task.completed_at,
+            ..Self::default()
+        }
+    }
+}
+
+// a row of the CSV export. times are local, ex: "2024-05-17 09:30", so that spreadsheets can
+// show them, and every column but the title can be left empty on import
+#[derive(Serialize, Deserialize)]
+struct CsvTask {
+    #[serde(default)]
+    id: Option<u64>,
+    title: String,
+    #[serde(default)]
+    completed: String,
+    #[serde(default)]
+    priority: String,
+    #[serde(default)]
+    due: String,
+    // space separated
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    project: String,
+    #[serde(default)]
+    recurrence: String,
+    #[serde(default)]
+    parent: Option<u64>,
+    #[serde(default)]
+    created_at: String,
+    #[serde(default)]
+    updated_at: String,
+    #[serde(default)]
+    completed_at: String,
+}
+
+impl CsvTask {
+    fn new(task: &Task, now: OffsetDateTime) -> Self {
+        let time = |timestamp: Option<i64>| {
+            timestamp
+                .and_then(|t| format_due_input(t, now))
+                .unwrap_or_default()
+        };
+        Self {
+            id: Some(task.id),
+            title: task.title.clone(),
+            completed: task.completed.to_string(),
+            priority: task.priority.map(|p| p.to_string()).unwrap_or_default(),
+            due: time(task.due),
+            tags: task.tags.join(" "),
+            project: task.project.clone().unwrap_or_default(),
+            recurrence: task.recurrence.map(|r| r.to_string()).unwrap_or_default(),
+            parent: task.parent,
+            created_at: time(task.created_at),
+            updated_at: time(task.updated_at),
+            completed_at: time(task.completed_at),
+        }
+    }
+
+    fn into_task(self, now: OffsetDateTime) -> Result<Task, String> {
+        let time = |text: &str| {
+            let text = text.trim();
+            (!text.is_empty()).then(|| parse_due(text, now)).transpose()
+        };
+        let title = self.title.trim();
+        if title.is_empty() {
+            return Err("title can't be empty".to_string());
+        }
+        Ok(Task {
+            id: self.id.unwrap_or_default(),
+            title: title.to_string(),
+            completed: matches!(
+                self.completed.trim().to_lowercase().as_str(),
+                "true" | "yes" | "x" | "1" | "done"
+            ),
+            priority: optional(&self.priority).map(str::parse).transpose()?,
+            due: time(&self.due)?,
+            tags: self
+                .tags
+                .split_whitespace()
+                .map(|tag| tag.trim_start_matches('+').to_lowercase())
+                .filter(|tag| !tag.is_empty())
+                .collect(),
+            project: optional(self.project.trim_start_matches('@')).map(str::to_lowercase),
+            recurrence: optional(&self.recurrence).map(str::parse).transpose()?,
+            parent: self.parent,
+            created_at: time(&self.created_at)?,
+            updated_at: time(&self.updated_at)?,
+            completed_at: time(&self.completed_at)?,
+            ..Task::default()
+        })
+    }
+}
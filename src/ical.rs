@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+
+use time::{
+    macros::format_description, Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset,
+    Weekday,
+};
+
+use crate::{due::END_OF_DAY, priority::Priority, recur::Recurrence, Task};
+
+/// The UID of a task's VTODO. Tasks imported from a calendar keep the UID they came with, so
+/// that the calendar recognizes them when they are exported back to it.
+pub fn uid(task: &Task) -> String {
+    task.uid.clone().unwrap_or_else(|| {
+        format!(
+            "pls-{}-{}@pls",
+            task.id,
+            task.created_at.unwrap_or_default()
+        )
+    })
+}
+
+/// Write tasks as a VCALENDAR of VTODOs.
+pub fn export(tasks: &[Task], now: OffsetDateTime) -> String {
+    calendar(tasks.iter().flat_map(|task| vtodo(task, tasks, now)))
+}
+
+/// Write a task as a VCALENDAR of its own, as calendar servers keep every VTODO in a separate
+/// resource. Its parent is looked up in `tasks`.
+pub fn export_one(task: &Task, tasks: &[Task], now: OffsetDateTime) -> String {
+    calendar(vtodo(task, tasks, now).into_iter())
+}
+
+fn calendar(vtodos: impl Iterator<Item = String>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//pls//pls//EN".to_string(),
+    ];
+    lines.extend(vtodos);
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+fn vtodo(task: &Task, tasks: &[Task], now: OffsetDateTime) -> Vec<String> {
+    let mut lines = vec![
+        "BEGIN:VTODO".to_string(),
+        format!("UID:{}", escape(&uid(task))),
+    ];
+    lines.extend(format_utc(now.unix_timestamp()).map(|t| format!("DTSTAMP:{t}")));
+    lines.push(format!("SUMMARY:{}", escape(&task.title)));
+    lines.push(format!(
+        "STATUS:{}",
+        if task.completed {
+            "COMPLETED"
+        } else {
+            "NEEDS-ACTION"
+        }
+    ));
+    let times = [
+        ("DUE", task.due),
+        ("CREATED", task.created_at),
+        ("LAST-MODIFIED", task.updated_at),
+        ("COMPLETED", task.completed_at),
+    ];
+    for (name, timestamp) in times {
+        lines.extend(
+            timestamp
+                .and_then(format_utc)
+                .map(|t| format!("{name}:{t}")),
+        );
+    }
+    if let Some(priority) = task.priority {
+        lines.push(format!("PRIORITY:{}", ical_priority(priority)));
+    }
+    if !task.tags.is_empty() {
+        let tags = task.tags.iter().map(|t| escape(t)).collect::<Vec<_>>();
+        lines.push(format!("CATEGORIES:{}", tags.join(",")));
+    }
+    if let Some(project) = &task.project {
+        lines.push(format!("X-PLS-PROJECT:{}", escape(project)));
+    }
+    if let Some(recurrence) = task.recurrence {
+        lines.push(format!("RRULE:{}", rrule(recurrence)));
+    }
+    if let Some(parent) = task.parent.and_then(|id| tasks.iter().find(|t| t.id == id)) {
+        lines.push(format!("RELATED-TO:{}", escape(&uid(parent))));
+    }
+    lines.push("END:VTODO".to_string());
+    lines
+}
+
+/// Read the VTODOs of a calendar. The tasks get ids counting up from 1 that subtasks' parents
+/// refer to, and keep their UID so that importing them again updates them.
+pub fn import(text: &str, offset: UtcOffset) -> Result<Vec<Task>, String> {
+    let mut tasks = Vec::new();
+    // the parent of each task, as the parent's UID
+    let mut related = Vec::new();
+    let mut current: Option<(Task, Option<String>)> = None;
+    for (number, line) in unfold(text).iter().enumerate() {
+        let error = |e: &str| format!("Invalid iCalendar on line {}: {e}", number + 1);
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        // parameters, ex: DUE;VALUE=DATE:20240517
+        let (name, params) = name.split_once(';').unwrap_or((name, ""));
+        match (name.to_uppercase().as_str(), current.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VTODO") => {
+                current = Some((Task::default(), None));
+            }
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VTODO") => {
+                let (mut task, parent) = current.take().ok_or_else(|| error("stray END"))?;
+                task.id = tasks.len() as u64 + 1;
+                if task.title.is_empty() {
+                    return Err(error("VTODO without a SUMMARY"));
+                }
+                tasks.push(task);
+                related.push(parent);
+            }
+            (name, Some((task, parent))) => match name {
+                "UID" => task.uid = Some(unescape(value)),
+                "SUMMARY" => task.title = unescape(value).trim().to_string(),
+                "STATUS" => task.completed = value.eq_ignore_ascii_case("COMPLETED"),
+                "DUE" => {
+                    let due = parse_time(value, params, offset).ok_or_else(|| error("bad DUE"))?;
+                    task.due = Some(due);
+                }
+                "CREATED" => task.created_at = parse_time(value, params, offset),
+                "LAST-MODIFIED" => task.updated_at = parse_time(value, params, offset),
+                "COMPLETED" => task.completed_at = parse_time(value, params, offset),
+                "PRIORITY" => {
+                    let priority = value
+                        .trim()
+                        .parse::<u8>()
+                        .map_err(|_| error("bad PRIORITY"))?;
+                    task.priority = priority_from_ical(priority);
+                }
+                "CATEGORIES" => {
+                    for tag in split_list(value) {
+                        let tag = tag.trim().to_lowercase().replace(' ', "-");
+                        if !tag.is_empty() && !task.tags.contains(&tag) {
+                            task.tags.push(tag);
+                        }
+                    }
+                }
+                "X-PLS-PROJECT" => task.project = Some(unescape(value).to_lowercase()),
+                "RRULE" => task.recurrence = parse_rrule(value),
+                "RELATED-TO" if !params.to_uppercase().contains("RELTYPE=CHILD") => {
+                    *parent = Some(unescape(value));
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    // point parents at the ids given to the tasks above
+    let ids = tasks
+        .iter()
+        .filter_map(|t| Some((t.uid.clone()?, t.id)))
+        .collect::<HashMap<_, _>>();
+    for (task, parent) in tasks.iter_mut().zip(related) {
+        task.parent = parent.and_then(|uid| ids.get(&uid).copied());
+        if task.completed && task.completed_at.is_none() {
+            task.completed_at = task.updated_at;
+        }
+    }
+    Ok(tasks)
+}
+
+// iCalendar priorities go from 1 (highest) to 9 (lowest), 0 meaning none
+const fn ical_priority(priority: Priority) -> u8 {
+    match priority {
+        Priority::High => 1,
+        Priority::Medium => 5,
+        Priority::Low => 9,
+    }
+}
+
+const fn priority_from_ical(priority: u8) -> Option<Priority> {
+    match priority {
+        1..=4 => Some(Priority::High),
+        5 => Some(Priority::Medium),
+        6..=9 => Some(Priority::Low),
+        _ => None,
+    }
+}
+
+const ICAL_WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Monday),
+    ("TU", Weekday::Tuesday),
+    ("WE", Weekday::Wednesday),
+    ("TH", Weekday::Thursday),
+    ("FR", Weekday::Friday),
+    ("SA", Weekday::Saturday),
+    ("SU", Weekday::Sunday),
+];
+
+fn rrule(recurrence: Recurrence) -> String {
+    match recurrence {
+        Recurrence::Daily => "FREQ=DAILY".to_string(),
+        Recurrence::Weekday => "FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR".to_string(),
+        Recurrence::Weekly(weekday) => {
+            let day = ICAL_WEEKDAYS
+                .iter()
+                .find(|(_, w)| *w == weekday)
+                .map_or("MO", |(name, _)| name);
+            format!("FREQ=WEEKLY;BYDAY={day}")
+        }
+        Recurrence::Monthly(day) => format!("FREQ=MONTHLY;BYMONTHDAY={day}"),
+    }
+}
+
+// only the rules pls can represent are understood, anything else is dropped
+fn parse_rrule(rule: &str) -> Option<Recurrence> {
+    let parts = rule
+        .split(';')
+        .filter_map(|part| part.split_once('='))
+        .map(|(key, value)| (key.to_uppercase(), value.to_uppercase()))
+        .collect::<HashMap<_, _>>();
+    if parts
+        .keys()
+        .any(|key| key != "FREQ" && key != "BYDAY" && key != "BYMONTHDAY")
+    {
+        return None;
+    }
+    let byday = parts.get("BYDAY").map(String::as_str);
+    match (parts.get("FREQ")?.as_str(), byday) {
+        ("DAILY", None) => Some(Recurrence::Daily),
+        ("WEEKLY", Some("MO,TU,WE,TH,FR")) => Some(Recurrence::Weekday),
+        ("WEEKLY", Some(day)) => ICAL_WEEKDAYS
+            .iter()
+            .find(|(name, _)| *name == day)
+            .map(|(_, weekday)| Recurrence::Weekly(*weekday)),
+        ("MONTHLY", None) => parts
+            .get("BYMONTHDAY")?
+            .parse::<u8>()
+            .ok()
+            .filter(|day| (1..=31).contains(day))
+            .map(Recurrence::Monthly),
+        _ => None,
+    }
+}
+
+/// A time as iCalendar writes it in UTC, ex: "20240517T093000Z".
+pub fn format_utc(timestamp: i64) -> Option<String> {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .ok()?
+        .format(format_description!(
+            "[year][month][day]T[hour][minute][second]Z"
+        ))
+        .ok()
+}
+
+/// Parse an iCalendar time. Accepts UTC times (20240517T093000Z), floating or TZID times, which
+/// are taken to be local, and dates (20240517), which are due at the end of the day.
+pub fn parse_time(value: &str, params: &str, offset: UtcOffset) -> Option<i64> {
+    let value = value.trim();
+    let number = |range: std::ops::Range<usize>| value.get(range)?.parse::<u32>().ok();
+    let date = Date::from_calendar_date(
+        i32::try_from(number(0..4)?).ok()?,
+        Month::try_from(u8::try_from(number(4..6)?).ok()?).ok()?,
+        u8::try_from(number(6..8)?).ok()?,
+    )
+    .ok()?;
+    if value.len() == 8 || params.to_uppercase().contains("VALUE=DATE") {
+        return Some(
+            date.with_time(END_OF_DAY)
+                .assume_offset(offset)
+                .unix_timestamp(),
+        );
+    }
+    if value.get(8..9) != Some("T") {
+        return None;
+    }
+    let time = Time::from_hms(
+        u8::try_from(number(9..11)?).ok()?,
+        u8::try_from(number(11..13)?).ok()?,
+        u8::try_from(number(13..15)?).ok()?,
+    )
+    .ok()?;
+    let time = PrimitiveDateTime::new(date, time);
+    let offset = if value.ends_with('Z') {
+        UtcOffset::UTC
+    } else {
+        offset
+    };
+    Some(time.assume_offset(offset).unix_timestamp())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n' | 'N') => out.push('\n'),
+                Some(c) => out.push(c),
+                None => {}
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// split a comma separated list, leaving escaped commas in place
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        if c == ',' && !escaped {
+            items.push(String::new());
+            continue;
+        }
+        escaped = c == '\\' && !escaped;
+        if let Some(item) = items.last_mut() {
+            item.push(c);
+        }
+    }
+    items.iter().map(|item| unescape(item)).collect()
+}
+
+// lines longer than 75 bytes are folded onto continuation lines starting with a space
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
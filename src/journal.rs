@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use time::{macros::format_description, OffsetDateTime};
+use yansi::Paint;
+
+use crate::{
+    get_time,
+    merge::{self, Tombstone},
+    store::Store,
+    Task,
+};
+
+// number of operations kept when the history-size setting isn't set
+const DEFAULT_HISTORY_SIZE: usize = 50;
+
+/// A change to the task list, with snapshots of the list before and after it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub timestamp: i64,
+    pub command: String,
+    pub before: Vec<Task>,
+    pub after: Vec<Task>,
+}
+
+impl Entry {
+    fn summary(&self) -> String {
+        summary(&self.before, &self.after)
+    }
+}
+
+/// Short description of what changed between two task lists, ex: "1 added, 2 changed". Empty
+/// if nothing did.
+pub fn summary(before: &[Task], after: &[Task]) -> String {
+    let added = after
+        .iter()
+        .filter(|t| !before.iter().any(|b| b.id == t.id))
+        .count();
+    let removed = before
+        .iter()
+        .filter(|t| !after.iter().any(|a| a.id == t.id))
+        .count();
+    let changed = after
+        .iter()
+        .filter(|t| before.iter().any(|b| b.id == t.id && b != *t))
+        .count();
+    [(added, "added"), (removed, "removed"), (changed, "changed")]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, what)| format!("{count} {what}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Save the task list, recording the change in the journal so that it can be reverted.
+/// `before` and `after` can be the tasks of a single list: tasks that aren't in `before` are
+/// kept as they are, and the journal records the whole task list.
+///
+/// Only what changed between `before` and `after` is applied, by id, to the tasks as they are
+/// when the database is locked, so that what another pls process saved since `before` was read
+/// isn't undone. A task changed by both keeps the change saved last.
+pub fn save_tasks(db: &mut dyn Store, command: &str, before: &[Task], after: &[Task]) {
+    db.begin().expect("Failed to start transaction");
+    let all_before = db.tasks();
+    // the version of each stored task in `after`: the same task if it's still there, ex: when
+    // several have the same id, or else the first with its id
+    let mut used = vec![false; after.len()];
+    let mut pairs = vec![None; all_before.len()];
+    for exact in [true, false] {
+        for (current, pair) in all_before.iter().zip(&mut pairs) {
+            if pair.is_none() {
+                *pair = (0..after.len()).find(|&i| {
+                    !used[i]
+                        && if exact {
+                            after[i] == *current
+                        } else {
+                            after[i].id == current.id
+                        }
+                });
+                pair.inspect(|&i| used[i] = true);
+            }
+        }
+    }
+    // in the order they are stored: tasks this command left as they were stay as they are now,
+    // and the ones it removed are dropped
+    let mut all_after = Vec::new();
+    for (current, pair) in all_before.iter().zip(pairs) {
+        match pair.map(|i| &after[i]) {
+            Some(task) if !before.contains(task) => all_after.push(task.clone()),
+            None if before.iter().any(|b| b.id == current.id) => {}
+            _ => all_after.push(current.clone()),
+        }
+    }
+    // followed by the ones it added, or changed after another process removed them
+    all_after.extend(
+        after
+            .iter()
+            .zip(used)
+            .filter(|(task, used)| !used && !before.contains(task))
+            .map(|(task, _)| task.clone()),
+    );
+    // ex: a due date on a task of a linked checklist, which has no place for it
+    if let Err(e) = db.set_tasks(&all_after) {
+        db.commit().expect("Failed to save tasks");
+        println!("{}", Paint::red(&format!("Error: {e}")));
+        return;
+    }
+    if before == after {
+        db.commit().expect("Failed to save tasks");
+        return;
+    }
+    let timestamp = get_time().unix_timestamp();
+    // removed tasks leave a tombstone, so that `pls merge` doesn't bring them back
+    let removed = before
+        .iter()
+        .filter(|b| !after.iter().any(|a| a.id == b.id))
+        .map(|t| Tombstone::new(t, timestamp))
+        .collect::<Vec<_>>();
+    if !removed.is_empty() {
+        merge::bury(db, &removed).expect("Failed to set tombstones");
+    }
+    let mut journal = get_journal(db, "journal");
+    journal.push(Entry {
+        timestamp,
+        command: command.to_string(),
+        before: all_before,
+        after: all_after,
+    });
+    // only the most recent operations are kept
+    let size = history_size(db);
+    if journal.len() > size {
+        journal.drain(..journal.len() - size);
+    }
+    db.set("journal", &journal).expect("Failed to set journal");
+    // a new change means the reverted operations can't be redone anymore
+    if db.exists("journal-redo") {
+        db.rem("journal-redo")
+            .expect("Failed to remove redo journal");
+    }
+    db.commit().expect("Failed to save tasks");
+}
+
+/// Undo the last `count` operations, moving them to the redo journal.
+/// Returns the reverted entries, most recent first.
+pub fn revert(db: &mut dyn Store, count: usize) -> Vec<Entry> {
+    move_entries(db, count, "journal", "journal-redo", |entry| &entry.before)
+}
+
+/// Reapply the last `count` reverted operations. Returns the redone entries, oldest first.
+pub fn redo(db: &mut dyn Store, count: usize) -> Vec<Entry> {
+    move_entries(db, count, "journal-redo", "journal", |entry| &entry.after)
+}
+
+// pop entries from one journal onto the other, and restore the snapshot chosen by `restore`
+// from the last entry popped
+fn move_entries(
+    db: &mut dyn Store,
+    count: usize,
+    from: &str,
+    to: &str,
+    restore: fn(&Entry) -> &Vec<Task>,
+) -> Vec<Entry> {
+    let mut source = get_journal(db, from);
+    let mut target = get_journal(db, to);
+    let moved = (0..count).map_while(|_| source.pop()).collect::<Vec<_>>();
+    if let Some(last) = moved.last() {
+        target.extend(moved.iter().cloned());
+        db.begin().expect("Failed to start transaction");
+        db.set_tasks(restore(last)).expect("Failed to set tasks");
+        db.set(from, &source).expect("Failed to set journal");
+        db.set(to, &target).expect("Failed to set journal");
+        db.commit().expect("Failed to save tasks");
+    }
+    moved
+}
+
+/// Print the journal, most recent operation first.
+pub fn print_history(db: &dyn Store) {
+    let journal = get_journal(db, "journal");
+    if journal.is_empty() {
+        println!("{}", Paint::green("No changes recorded yet!"));
+        return;
+    }
+    let format = format_description!("[year]-[month]-[day] [hour]:[minute]");
+    let offset = get_time().offset();
+    for (n, entry) in journal.iter().rev().enumerate() {
+        let time = OffsetDateTime::from_unix_timestamp(entry.timestamp)
+            .map(|t| t.to_offset(offset))
+            .ok()
+            .and_then(|t| t.format(&format).ok())
+            .unwrap_or_default();
+        println!(
+            "{:>3}. {} {} ({})",
+            Paint::yellow(&(n + 1)),
+            Paint::blue(&time),
+            Paint::green(&format!("pls {}", entry.command)),
+            entry.summary()
+        );
+    }
+    let redo = get_journal(db, "journal-redo");
+    if !redo.is_empty() {
+        println!(
+            "\n{} reverted operation(s) can be redone with {}",
+            redo.len(),
+            Paint::yellow("pls redo")
+        );
+    }
+}
+
+fn get_journal(db: &dyn Store, key: &str) -> Vec<Entry> {
+    db.get::<Vec<Entry>>(key).unwrap_or_default()
+}
+
+fn history_size(db: &dyn Store) -> usize {
+    db.setting("history-size")
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_SIZE)
+}
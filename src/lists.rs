@@ -0,0 +1,60 @@
+use yansi::Paint;
+
+use crate::Task;
+
+/// Name of the list tasks are in when no `--list` is given. Its tasks have no `list`.
+pub const DEFAULT: &str = "default";
+
+/// Check a list name given with `--list`, returning `None` for the default list.
+pub fn parse(name: &str) -> Result<Option<String>, String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!(
+            "Invalid list name \"{name}\", must be a single word"
+        ));
+    }
+    Ok((name != DEFAULT).then_some(name))
+}
+
+/// The name a list is shown with.
+pub fn name(list: Option<&str>) -> &str {
+    list.unwrap_or(DEFAULT)
+}
+
+/// The tasks in a list, in the order they were added.
+pub fn tasks_in(tasks: &[Task], list: Option<&str>) -> Vec<Task> {
+    tasks
+        .iter()
+        .filter(|t| t.list.as_deref() == list)
+        .cloned()
+        .collect()
+}
+
+/// Every list that has tasks, with the default list first and the others by name. The default
+/// list is always included, even when it is empty.
+pub fn all(tasks: &[Task]) -> Vec<Option<String>> {
+    let mut lists = tasks
+        .iter()
+        .filter_map(|t| t.list.clone())
+        .collect::<Vec<_>>();
+    lists.sort();
+    lists.dedup();
+    std::iter::once(None)
+        .chain(lists.into_iter().map(Some))
+        .collect()
+}
+
+/// Print every list with how many pending and completed tasks it has, marking the current one.
+pub fn print_lists(tasks: &[Task], current: Option<&str>) {
+    for list in all(tasks) {
+        let in_list = tasks_in(tasks, list.as_deref());
+        let pending = in_list.iter().filter(|t| !t.completed).count();
+        println!(
+            "{} {} {} pending, {} completed",
+            if list.as_deref() == current { "*" } else { " " },
+            Paint::yellow(name(list.as_deref())),
+            Paint::red(&pending),
+            Paint::green(&(in_list.len() - pending))
+        );
+    }
+}
@@ -25,13 +25,18 @@
 #![allow(clippy::cognitive_complexity)]
 #![allow(clippy::too_many_lines)]
 
-use clap::{arg, ArgAction, Command};
+use clap::{arg, ArgAction, ArgMatches, Command};
 use directories_next::ProjectDirs;
 use native_tls::TlsConnector;
-use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
 use serde::{Deserialize, Serialize};
 use spinach::{term, Spinach};
-use std::{fs::DirBuilder, str::from_utf8, sync::Arc};
+use std::{
+    fs::{self, DirBuilder},
+    io::IsTerminal,
+    path::Path,
+    str::from_utf8,
+    sync::Arc,
+};
 use tegen::tegen::TextGenerator;
 use term_table::{
     row::Row,
@@ -40,11 +45,34 @@ use term_table::{
 };
 use time::{macros::format_description, OffsetDateTime};
 use ureq::Response;
-use yansi::Paint;
+use yansi::{Paint, Painted};
 
-use crate::weather::get_weather;
+use crate::{
+    filter::Filter, priority::Priority, recur::Recurrence, store::Store, weather::get_weather,
+};
+mod caldav;
+mod checklist;
+mod config;
+mod crypto;
+mod due;
+mod edit;
+mod filter;
+mod formats;
+mod ical;
+mod journal;
+mod lists;
+mod merge;
+mod migrate;
+mod priority;
 mod quotes;
+mod recur;
+mod scan;
+mod selector;
+mod store;
+mod sync;
+mod taskwarrior;
 mod tests;
+mod todotxt;
 mod weather;
 fn main() {
     // https://github.com/etienne-napoleone/spinach#how-to-avoid-leaving-terminal-without-prompt-on-interupt-ctrlc
@@ -57,19 +85,47 @@ fn main() {
     // create config directory
     create_dir();
 
-    // create path to config file
-    let path = ProjectDirs::from("com", "sigaloid", "pls")
-        .expect("Failed to create ProjectDirs!")
-        .config_dir()
-        .join("pls.json");
+    // create path to config directory
+    let dirs = ProjectDirs::from("com", "sigaloid", "pls").expect("Failed to create ProjectDirs!");
+
+    let matches = cli().get_matches();
+    // pls.json is encrypted and decrypted on its own, before it is opened
+    if let Some((name @ ("encrypt" | "decrypt" | "unlock" | "lock" | "agent"), sub_matches)) =
+        matches.subcommand()
+    {
+        if let Err(e) = encryption_command(name, sub_matches, dirs.config_dir()) {
+            println!("{}", Paint::red(&format!("Error: {e}")));
+        }
+        return;
+    }
+
+    // open database
+    let mut db = match store::open(dirs.config_dir()) {
+        Ok(db) => db,
+        Err(e) => {
+            println!("{}", Paint::red(&format!("Error: {e}")));
+            std::process::exit(1);
+        }
+    };
+    let db = db.as_mut();
 
-    // create database
-    let mut db = PickleDb::load_or_new(
-        path,
-        PickleDbDumpPolicy::AutoDump,
-        SerializationMethod::Json,
-    )
-    .expect("Failed to create database!");
+    // bring databases from older versions of pls up to date
+    match migrate::migrate(db, &dirs.config_dir().join("backups")) {
+        Ok(Some(upgrade)) => println!(
+            "{}",
+            Paint::green(&format!(
+                "Upgraded your tasks from version {} to {}, the old ones are backed up in {}",
+                upgrade.from,
+                migrate::SCHEMA_VERSION,
+                upgrade.backup.display()
+            ))
+        ),
+        Ok(None) => {}
+        Err(e) => {
+            println!("{}", Paint::red(&format!("Error: {e}")));
+            std::process::exit(1);
+        }
+    }
 
     // if name has not been set, ask for name and save it
     if !db.exists("name") {
@@ -127,69 +183,82 @@ fn main() {
             }
         }
     }
-    let matches = clap::Command::new("pls").version("0.1.0")
-        .propagate_version(true)
-        .subcommand_required(false)
-        .arg_required_else_help(false)
-        .subcommand(
-            Command::new("add")
-                .about("Add task to todo")
-                .arg(arg!([NAME])),
-        )
-        .subcommand(
-            Command::new("do")
-                .alias("done")
-                .about("Mark task as done")
-                .arg(arg!([INDEX])),
-        )
-        .subcommand(
-            Command::new("undo").alias("undone")
-                .about("Mark task as undone")
-                .arg(arg!([INDEX])),
-        )
-        .subcommand(
-            Command::new("rm")
-                .alias("remove")
-                .alias("del")
-                .alias("delete")
-                .about("Remove task")
-                .arg(arg!([INDEX])),
-        )
-        .subcommand(
-            Command::new("list")
-                .alias("ls")
-                .alias("all")
-                .about("List tasks"),
-        )
-        .subcommand(
-            Command::new("install")
-                .about("Install into shell. \"fish\", \"bash\", or \"zsh\" as options are supported. 
-                Alternatively, enter \"weather\" as an option to install a crontab service to automatically update the weather in the background.")
-                .arg(arg!([SHELL])),
-        )
-        .subcommand(Command::new("clean").about("Clean all completed tasks"))
-        .arg(
-            arg!(
-                -r --refresh "Force refresh of weather"
-            )
-            .action(ArgAction::SetTrue),
-        ).arg(
-            arg!(
-                -a --all "Apply change to all tasks"
-              )
-            .action(ArgAction::SetTrue),
-        ).arg(
-            arg!(
-                -w --weather "Just print the weather!"
-            ).action(ArgAction::SetTrue)
-        )
-        .get_matches();
     // bool that represents whether the command should apply changes to all tasks
     let all = *matches.get_one::<bool>("all").unwrap_or(&false);
     // bool that represents whether the weather should be refreshed
     let force_refresh = *matches.get_one::<bool>("refresh").unwrap_or(&false);
     // bool that represents just the weather to be printed
     let weather = *matches.get_one::<bool>("weather").unwrap_or(&false);
+    // the named list commands apply to, none for the default list
+    let list = match matches
+        .get_one::<String>("list")
+        .map(|list| lists::parse(list))
+    {
+        Some(Ok(list)) => list,
+        Some(Err(e)) => {
+            println!("{}", Paint::red(&format!("Error: {e}")));
+            return;
+        }
+        None => None,
+    };
+    let list = list.as_deref();
+    let view = View {
+        list: list.map(str::to_string),
+        ..View::default()
+    };
+    // a linked checklist file takes the place of the global tasks, except to change the link
+    let mut linked;
+    let db: &mut dyn Store = match db.get::<String>("linked-file") {
+        Some(path) if !matches!(matches.subcommand_name(), Some("link" | "unlink" | "sync")) => {
+            match store::LinkedStore::open(Path::new(&path), dirs.config_dir(), db) {
+                Ok(store) => {
+                    linked = store;
+                    &mut linked
+                }
+                Err(e) => {
+                    println!(
+                        "{}",
+                        Paint::red(&format!(
+                            "Error: {e}. Run `pls unlink` to go back to the tasks pls keeps itself"
+                        ))
+                    );
+                    return;
+                }
+            }
+        }
+        _ => db,
+    };
+    // the project's tasks are shown along with the global ones, and changed with --local
+    let local = matches.get_flag("local");
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let project_path = store::find_project(&cwd).or_else(|| {
+        // adding the first local task starts a project in the current directory
+        (local && matches.subcommand_name() == Some("add")).then(|| cwd.join(".pls.json"))
+    });
+    let key = db.key().cloned();
+    let opened = project_path.map(|path| open_project(&path, dirs.config_dir(), key.as_ref()));
+    let mut local_project = match opened {
+        Some(Ok(project)) => Some(project),
+        Some(Err(e)) => {
+            println!("{}", Paint::red(&format!("Error: {e}")));
+            return;
+        }
+        None if local => {
+            println!(
+                "{}",
+                Paint::red("Error: no .pls.json or .pls directory here or in any parent directory")
+            );
+            return;
+        }
+        None => None,
+    };
+    // the store that commands change tasks in
+    let tasks_db: &mut dyn Store = match local_project.as_mut() {
+        Some(project) if local => project.store.as_mut(),
+        _ => &mut *db,
+    };
+    // the command as typed, recorded in the journal by every command that changes tasks
+    let operation = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
     // match each subcommand
     match matches.subcommand() {
         Some(("add", sub_matches)) => {
@@ -198,128 +267,322 @@ fn main() {
                 || casual::prompt("Enter task: ").get(),
                 std::borrow::ToOwned::to_owned,
             );
+            // an inline priority marker is removed from the title, --priority takes precedence over it
+            let (task, inline_priority) = priority::strip_inline(&task);
+            // +tag and @project tokens are removed from the title too
+            let (task, tags, project) = filter::strip_tags(&task);
+            let mut new_task = Task::new(&task);
+            new_task.tags = tags;
+            new_task.project = project;
+            new_task.priority = match sub_matches.get_one::<String>("priority") {
+                Some(priority) => match priority.parse::<Priority>() {
+                    Ok(priority) => Some(priority),
+                    Err(e) => {
+                        println!("{}", Paint::red(&format!("Error: {e}")));
+                        return;
+                    }
+                },
+                None => inline_priority,
+            };
+            // parse due date, if specified, relative to the current local time
+            if let Some(due) = sub_matches.get_one::<String>("due") {
+                match due::parse_due(due, get_time()) {
+                    Ok(due) => new_task.due = Some(due),
+                    Err(e) => {
+                        println!("{}", Paint::red(&format!("Error: {e}")));
+                        return;
+                    }
+                }
+            }
+            if let Some(every) = sub_matches.get_one::<String>("every") {
+                match every.parse::<Recurrence>() {
+                    Ok(recurrence) => {
+                        new_task.recurrence = Some(recurrence);
+                        // without a due date, the task is first due on its first occurrence
+                        if new_task.due.is_none() {
+                            let now = get_time();
+                            let first = recurrence.first_date(now.date());
+                            new_task.due = Some(
+                                first
+                                    .with_time(due::END_OF_DAY)
+                                    .assume_offset(now.offset())
+                                    .unix_timestamp(),
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        println!("{}", Paint::red(&format!("Error: {e}")));
+                        return;
+                    }
+                }
+            }
+            let before = list_tasks(tasks_db, list);
+            if let Some(parent) = sub_matches.get_one::<String>("under") {
+                let Some(index) = find_task(&before, Some(parent)) else {
+                    println!(
+                        "{}",
+                        Paint::red(
+                            "Error: parent task not found. Are you sure a task exists with that id?"
+                        )
+                    );
+                    return;
+                };
+                new_task.parent = Some(before[index].id);
+            }
+            new_task.id = next_id(tasks_db);
+            new_task.list = list.map(str::to_string);
             println!("Adding task {} to list...", Paint::yellow(&task));
             // get copy of tasks, add new task, and save to database
-            let mut tasks = get_tasks(&db);
-            tasks.push(Task::new(&task));
-            db.set("tasks", &tasks).expect("Failed to set tasks");
-            print_tasks(&mut db, false, force_refresh, false);
+            let mut tasks = before.clone();
+            tasks.push(new_task);
+            journal::save_tasks(tasks_db, &operation, &before, &tasks);
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
         }
         Some(("do", sub_matches)) => {
-            // use specified index or default to first
-            if all {
+            // get copy of tasks, mark selected tasks (or all of them) as completed
+            let before = list_tasks(tasks_db, list);
+            let mut tasks = before.clone();
+            let selected = if all {
                 println!("{}", Paint::red("Marking all tasks as done..."));
-                // get copy of tasks, mark as completed, replace task in task list
-                let new_tasks = get_tasks(&db)
-                    .into_iter()
-                    .map(|x| make_complete(&x))
-                    .collect::<Vec<Task>>();
-
-                // save task list to database
-                db.set("tasks", &new_tasks).expect("Failed to set tasks");
+                Ok((0..tasks.len()).collect())
             } else {
-                let index = sub_matches
-                    .get_one::<String>("INDEX")
-                    .map_or_else(|| 0, |index| index.parse::<usize>().unwrap_or(0))
-                    .saturating_sub(1);
-
-                println!(
-                    "Marking task {} from list as done...",
-                    Paint::yellow(&(index + 1))
-                );
-                // get copy of tasks, mark as completed, replace task in task list
-                let mut tasks = get_tasks(&db);
-                match tasks.get_mut(index) {
-                    Some(task_mut) => {
-                        let mut task = task_mut.clone();
-                        task.completed = true;
-                        let _replace = std::mem::replace(&mut tasks[index], task);
+                selector::select(&tasks, &get_selector(sub_matches))
+            };
+            match selected {
+                Ok(indices) => {
+                    for index in indices {
+                        if tasks[index].completed {
+                            report(&tasks[index], Paint::yellow("already done"));
+                        } else if tasks[index].recurrence.is_some() {
+                            complete_recurring(tasks_db, &mut tasks, index);
+                            let next = due::format_due(
+                                tasks[index].due.unwrap_or_default(),
+                                false,
+                                get_time(),
+                            );
+                            report(
+                                &tasks[index],
+                                Paint::green(&format!("marked as done, next due {next}")),
+                            );
+                        } else {
+                            tasks[index] = tasks[index].make_complete();
+                            report(&tasks[index], Paint::green("marked as done"));
+                            // offer to finish the subtasks too, unless all tasks are being done
+                            let unfinished = subtasks(&tasks, tasks[index].id)
+                                .into_iter()
+                                .filter(|&i| !tasks[i].completed)
+                                .collect::<Vec<_>>();
+                            // only asked in a terminal, so that scripts never wait for an answer
+                            if !all
+                                && !unfinished.is_empty()
+                                && std::io::stdin().is_terminal()
+                                && casual::confirm(
+                                    Paint::blue(&format!(
+                                        "Task {} has {} unfinished subtasks. Mark them done too?",
+                                        tasks[index].id,
+                                        unfinished.len()
+                                    ))
+                                    .to_string(),
+                                )
+                            {
+                                for i in unfinished {
+                                    tasks[i] = tasks[i].make_complete();
+                                    report(&tasks[i], Paint::green("marked as done"));
+                                }
+                            }
+                        }
                     }
-                    None => println!(
-                        "{}",
-                        Paint::red(
-                            "Error: task not found. Are you sure a task exists with that number?"
-                        )
-                    ),
+                    // save task list to database
+                    journal::save_tasks(tasks_db, &operation, &before, &tasks);
                 }
-                // save task list to database
-                db.set("tasks", &tasks).expect("Failed to set tasks");
+                Err(e) => println!("{}", Paint::red(&format!("Error: {e}"))),
             }
-            print_tasks(&mut db, false, force_refresh, false);
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
         }
         Some(("undo", sub_matches)) => {
             if all {
                 println!("{}", Paint::red("Marking all tasks as undone..."));
                 // get copy of tasks, mark as uncompleted, replace task in task list
-                let tasks = get_tasks(&db);
+                let before = list_tasks(tasks_db, list);
                 let mut new_tasks = Vec::new();
-                for task in tasks {
-                    let mut new_task = task.clone();
-                    new_task.completed = false;
-                    new_tasks.push(new_task);
+                for task in &before {
+                    new_tasks.push(if task.completed {
+                        task.make_incomplete()
+                    } else {
+                        task.clone()
+                    });
                 }
                 // save task list to database
-                db.set("tasks", &new_tasks).expect("Failed to set tasks");
+                journal::save_tasks(tasks_db, &operation, &before, &new_tasks);
             } else {
-                // use specified index or default to first
-                let index = sub_matches
-                    .get_one::<String>("INDEX")
-                    .map_or_else(|| 0, |index| index.parse::<usize>().unwrap_or(0))
-                    .saturating_sub(1);
-
-                println!(
-                    "Marking task {} from list as undone...",
-                    Paint::yellow(&(index + 1))
-                );
-                // get copy of tasks, mark as uncompleted, replace task in task list
-                let mut tasks = get_tasks(&db);
-                match tasks.get_mut(index) {
-                    Some(task_mut) => {
-                        // set task as completed and replace it in task list
-                        let mut task = task_mut.clone();
-                        task.completed = false;
-                        let _replace = std::mem::replace(&mut tasks[index], task);
+                // get copy of tasks, mark selected tasks as uncompleted
+                let before = list_tasks(tasks_db, list);
+                let mut tasks = before.clone();
+                match selector::select(&tasks, &get_selector(sub_matches)) {
+                    Ok(indices) => {
+                        for index in indices {
+                            if tasks[index].completed {
+                                tasks[index] = tasks[index].make_incomplete();
+                                report(&tasks[index], Paint::green("marked as undone"));
+                            } else {
+                                report(&tasks[index], Paint::yellow("wasn't done"));
+                            }
+                        }
+                        // save task list to database
+                        journal::save_tasks(tasks_db, &operation, &before, &tasks);
                     }
-                    None => println!(
-                        "{}",
-                        Paint::red(
-                            "Error: task not found. Are you sure a task exists with that number?"
-                        )
-                    ),
+                    Err(e) => println!("{}", Paint::red(&format!("Error: {e}"))),
                 }
-                // save task list to database
-                db.set("tasks", &tasks).expect("Failed to set tasks");
             }
-            print_tasks(&mut db, false, force_refresh, false);
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
         }
         Some(("rm", sub_matches)) => {
             if all {
                 println!("{}", Paint::red("Removing all tasks..."));
-                db.rem("tasks").expect("Failed to remove tasks");
+                let before = list_tasks(tasks_db, list);
+                journal::save_tasks(tasks_db, &operation, &before, &[]);
             } else {
-                // use specified index or default to first
-                let index = sub_matches
-                    .get_one::<String>("INDEX")
-                    .map_or_else(|| 0, |index| index.parse::<usize>().unwrap_or(0))
-                    .saturating_sub(1);
-
-                println!("Removing task {}...", Paint::yellow(&(index + 1)));
-                // get copy of tasks, delete from list
-                let mut tasks = get_tasks(&db);
-                if tasks.get(index).is_some() {
-                    tasks.remove(index);
-                } else {
-                    println!(
-                        "{}",
-                        Paint::red(
-                            "Error: task not found. Are you sure a task exists with that number?"
-                        )
-                    );
+                // get copy of tasks, delete selected tasks from list
+                let before = list_tasks(tasks_db, list);
+                match selector::select(&before, &get_selector(sub_matches)) {
+                    Ok(indices) => {
+                        let mut kept = Vec::new();
+                        for (index, task) in before.iter().enumerate() {
+                            if indices.contains(&index) {
+                                report(task, Paint::red("removed"));
+                            } else {
+                                kept.push(task.clone());
+                            }
+                        }
+                        // save task list to database
+                        journal::save_tasks(tasks_db, &operation, &before, &kept);
+                    }
+                    Err(e) => println!("{}", Paint::red(&format!("Error: {e}"))),
                 }
-                // save task list to database
-                db.set("tasks", &tasks).expect("Failed to set tasks");
             }
-            print_tasks(&mut db, false, force_refresh, false);
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
+        }
+        Some(("edit", sub_matches)) => {
+            let before = list_tasks(tasks_db, list);
+            let mut tasks = before.clone();
+            let Some(index) = find_task(&tasks, sub_matches.get_one::<String>("ID")) else {
+                println!(
+                    "{}",
+                    Paint::red("Error: task not found. Are you sure a task exists with that id?")
+                );
+                return;
+            };
+            let task = &tasks[index];
+            // without a title, edit every field in $EDITOR
+            let edited = sub_matches.get_one::<String>("TITLE").map_or_else(
+                || edit::edit_in_editor(task),
+                // a new title can carry the same priority, +tag and @project tokens as `add`,
+                // which replace the current ones when present
+                |title| {
+                    let (title, priority) = priority::strip_inline(title);
+                    let (title, tags, project) = filter::strip_tags(&title);
+                    if title.is_empty() {
+                        return Err("Invalid task: title can't be empty".to_string());
+                    }
+                    Ok(Task {
+                        title,
+                        priority: priority.or(task.priority),
+                        tags: if tags.is_empty() {
+                            task.tags.clone()
+                        } else {
+                            tags
+                        },
+                        project: project.or_else(|| task.project.clone()),
+                        ..task.clone()
+                    })
+                },
+            );
+            match edited {
+                Ok(mut edited) => {
+                    println!("Updating task {}...", Paint::yellow(&edited.id));
+                    edited.touch();
+                    tasks[index] = edited;
+                    journal::save_tasks(tasks_db, &operation, &before, &tasks);
+                }
+                Err(e) => println!("{}", Paint::red(&format!("Error: {e}"))),
+            }
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
+        }
+        Some(("prio", sub_matches)) => {
+            let level = sub_matches
+                .get_one::<String>("PRIORITY")
+                .map_or("", String::as_str);
+            let priority = if level == "none" {
+                None
+            } else {
+                match level.parse::<Priority>() {
+                    Ok(priority) => Some(priority),
+                    Err(e) => {
+                        println!("{}", Paint::red(&format!("Error: {e}")));
+                        return;
+                    }
+                }
+            };
+            let before = list_tasks(tasks_db, list);
+            let mut tasks = before.clone();
+            let Some(index) = find_task(&tasks, sub_matches.get_one::<String>("ID")) else {
+                println!(
+                    "{}",
+                    Paint::red("Error: task not found. Are you sure a task exists with that id?")
+                );
+                return;
+            };
+            println!(
+                "Setting priority of task {} to {}...",
+                Paint::yellow(&tasks[index].id),
+                Paint::yellow(&level)
+            );
+            tasks[index].priority = priority;
+            tasks[index].touch();
+            journal::save_tasks(tasks_db, &operation, &before, &tasks);
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
         }
         Some(("install", sub_matches)) => {
             // code to manage installing to shell
@@ -371,32 +634,496 @@ fn main() {
         Some(("clean", _)) => {
             // remove all completed tasks
             println!("{}", Paint::blue("Clearing all completed tasks"));
-            let tasks = get_tasks(&db);
-            let prior_len = tasks.len();
-            let cleaned_tasks = tasks
-                .into_iter()
-                .filter(|t| !t.completed)
+            let before = list_tasks(tasks_db, list);
+            // recurring tasks are never removed, even if they were marked as done by hand
+            let cleaned_tasks = before
+                .iter()
+                .filter(|t| !t.completed || t.recurrence.is_some())
+                .cloned()
                 .collect::<Vec<_>>();
-            db.set("tasks", &cleaned_tasks)
-                .expect("Failed to set tasks");
+            journal::save_tasks(tasks_db, &operation, &before, &cleaned_tasks);
             println!(
                 "Cleaned {} completed tasks!",
-                Paint::green(&(prior_len - cleaned_tasks.len()))
+                Paint::green(&(before.len() - cleaned_tasks.len()))
+            );
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
             );
-            print_tasks(&mut db, false, force_refresh, false);
         }
-        Some(("list", _)) => {
-            // list all tasks without full greeting
-            print_tasks(&mut db, false, force_refresh, false);
+        Some(("history", _)) => {
+            journal::print_history(tasks_db);
+        }
+        Some(("revert", sub_matches)) => {
+            let count = *sub_matches.get_one::<usize>("COUNT").unwrap_or(&1);
+            let reverted = journal::revert(tasks_db, count);
+            if reverted.is_empty() {
+                println!("{}", Paint::red("Error: there is nothing to revert"));
+            }
+            for entry in &reverted {
+                println!(
+                    "Reverted {}",
+                    Paint::yellow(&format!("pls {}", entry.command))
+                );
+            }
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
+        }
+        Some(("redo", sub_matches)) => {
+            let count = *sub_matches.get_one::<usize>("COUNT").unwrap_or(&1);
+            let redone = journal::redo(tasks_db, count);
+            if redone.is_empty() {
+                println!("{}", Paint::red("Error: there is nothing to redo"));
+            }
+            for entry in &redone {
+                println!("Redid {}", Paint::yellow(&format!("pls {}", entry.command)));
+            }
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
+        }
+        Some(("export", sub_matches)) => {
+            let output = sub_matches.get_one::<String>("output");
+            let format = sub_matches.get_one::<String>("format").map_or_else(
+                || {
+                    Ok(output
+                        .and_then(|path| formats::Format::from_path(path.as_ref()))
+                        .unwrap_or(formats::Format::TodoTxt))
+                },
+                |format| format.parse(),
+            );
+            let tasks = list_tasks(tasks_db, list);
+            let exported = format.and_then(|format| formats::export(&tasks, format, get_time()));
+            let exported = match exported {
+                Ok(exported) => exported,
+                Err(e) => {
+                    println!("{}", Paint::red(&format!("Error: {e}")));
+                    return;
+                }
+            };
+            match output {
+                Some(path) => match store::write_atomic(path.as_ref(), exported.as_bytes()) {
+                    Ok(()) => println!(
+                        "Exported {} tasks to {}",
+                        Paint::green(&tasks.len()),
+                        Paint::yellow(path)
+                    ),
+                    Err(e) => println!("{}", Paint::red(&format!("Error: {e}"))),
+                },
+                None => print!("{exported}"),
+            }
+        }
+        Some(("import", sub_matches)) => {
+            let Some(path) = sub_matches.get_one::<String>("FILE") else {
+                return;
+            };
+            let text = match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(e) => {
+                    println!(
+                        "{}",
+                        Paint::red(&format!("Error: Failed to read {path}: {e}"))
+                    );
+                    return;
+                }
+            };
+            let imported = sub_matches
+                .get_one::<String>("format")
+                .map(|format| format.parse())
+                .or_else(|| formats::Format::from_path(path.as_ref()).map(Ok))
+                .unwrap_or_else(|| {
+                    Err(format!(
+                        "can't tell the format of {path}, give it with --format"
+                    ))
+                })
+                .and_then(|format| formats::import(&text, format, get_time()));
+            let formats::Imported {
+                tasks: mut imported,
+                unmapped,
+            } = match imported {
+                Ok(imported) => imported,
+                Err(e) => {
+                    println!("{}", Paint::red(&format!("Error: {e}")));
+                    return;
+                }
+            };
+            for task in &mut imported {
+                task.list = list.map(str::to_string);
+            }
+            let before = list_tasks(tasks_db, list);
+            let mut tasks = before.clone();
+            let counts = formats::add_imported(&mut tasks, imported, || next_id(tasks_db));
+            journal::save_tasks(tasks_db, &operation, &before, &tasks);
+            println!(
+                "Imported {} tasks from {}",
+                Paint::green(&counts.added),
+                Paint::yellow(path)
+            );
+            if counts.updated > 0 {
+                println!(
+                    "Updated {} tasks imported before",
+                    Paint::yellow(&counts.updated)
+                );
+            }
+            if counts.skipped > 0 {
+                println!(
+                    "Skipped {} tasks already in the list",
+                    Paint::yellow(&counts.skipped)
+                );
+            }
+            if !unmapped.is_empty() {
+                let fields = unmapped
+                    .iter()
+                    .map(|(field, count)| format!("{field} ({count} tasks)"))
+                    .collect::<Vec<_>>();
+                println!(
+                    "Couldn't import these fields: {}",
+                    Paint::yellow(&fields.join(", "))
+                );
+            }
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
+        }
+        Some(("config", sub_matches)) => {
+            config::config(
+                db,
+                sub_matches.get_one::<String>("KEY"),
+                sub_matches.get_one::<String>("VALUE"),
+            );
+        }
+        Some(("list", sub_matches)) => {
+            // list all tasks matching the filter without full greeting
+            match Filter::parse(sub_matches.get_many::<String>("FILTER").unwrap_or_default()) {
+                Ok(filter) => {
+                    let view = View {
+                        filter,
+                        long: sub_matches.get_flag("long"),
+                        all_lists: sub_matches.get_flag("all-lists"),
+                        ..view
+                    };
+                    print_tasks(
+                        db,
+                        local_project.as_ref(),
+                        false,
+                        force_refresh,
+                        false,
+                        &view,
+                    );
+                }
+                Err(e) => println!("{}", Paint::red(&format!("Error: {e}"))),
+            }
+        }
+        Some(("lists", _)) => {
+            lists::print_lists(&get_tasks(tasks_db), list);
+        }
+        Some(("link", sub_matches)) => {
+            let Some(file) = sub_matches.get_one::<String>("FILE") else {
+                match db.get::<String>("linked-file") {
+                    Some(path) => println!("Tasks are kept in {}", Paint::yellow(&path)),
+                    None => println!(
+                        "No file is linked, run {} to link one",
+                        Paint::yellow("pls link <FILE>")
+                    ),
+                }
+                return;
+            };
+            // kept as a full path, so that it is found from any directory
+            let path = cwd.join(file);
+            let path = fs::canonicalize(&path).unwrap_or(path);
+            let count = match store::LinkedStore::open(&path, dirs.config_dir(), db) {
+                Ok(linked) => linked.tasks().len(),
+                Err(e) => {
+                    println!("{}", Paint::red(&format!("Error: {e}")));
+                    return;
+                }
+            };
+            let path = path.to_string_lossy().to_string();
+            db.set("linked-file", &path).expect("Failed to link file");
+            println!(
+                "Linked {}, its {} task(s) are now your tasks",
+                Paint::yellow(&path),
+                Paint::green(&count)
+            );
+        }
+        Some(("unlink", _)) => {
+            let Some(path) = db.get::<String>("linked-file") else {
+                println!("{}", Paint::yellow("No file is linked"));
+                return;
+            };
+            db.rem("linked-file").expect("Failed to unlink file");
+            println!(
+                "Unlinked {}, the file is left as it is",
+                Paint::yellow(&path)
+            );
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
+        }
+        Some(("merge", sub_matches)) => {
+            let Some(path) = sub_matches.get_one::<String>("FILE") else {
+                return;
+            };
+            let strategy = sub_matches
+                .get_one::<String>("strategy")
+                .map_or_else(|| Ok(merge::Strategy::default()), |s| s.parse());
+            let opened = store::open_read_only(Path::new(path)).and_then(|mut other| {
+                migrate::upgrade(other.as_mut())
+                    .map(|()| other)
+                    .map_err(|e| format!("Can't merge {path}: {e}"))
+            });
+            let (strategy, other) = match (strategy, opened) {
+                (Ok(strategy), Ok(other)) => (strategy, other),
+                (Err(e), _) | (_, Err(e)) => {
+                    println!("{}", Paint::red(&format!("Error: {e}")));
+                    return;
+                }
+            };
+            let merged = merge_tasks(tasks_db, other.as_ref(), strategy, &operation);
+            println!(
+                "Merged {}: {} added, {} updated, {} removed, {} conflict(s)",
+                Paint::yellow(path),
+                Paint::green(&merged.added.len()),
+                Paint::green(&merged.updated.len()),
+                Paint::red(&merged.removed.len()),
+                Paint::yellow(&merged.conflicts.len())
+            );
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
+        }
+        Some(("sync", sub_matches)) => {
+            if let Some(("caldav", caldav_matches)) = sub_matches.subcommand() {
+                let synced = caldav::Account::new(
+                    db,
+                    caldav_matches.get_one::<String>("url"),
+                    caldav_matches.get_one::<String>("user"),
+                )
+                .and_then(|account| {
+                    caldav::sync(db, &account, &operation).map(|synced| (account.url, synced))
+                });
+                match synced {
+                    Ok((url, synced)) => {
+                        for (href, e) in &synced.skipped {
+                            println!("{}", Paint::yellow(&format!("Skipped {href}: {e}")));
+                        }
+                        let summary = journal::summary(&synced.before, &synced.after);
+                        println!(
+                            "Synced with {}: {}, {} uploaded, {} deleted, {} conflict(s)",
+                            Paint::yellow(&url),
+                            Paint::green(if summary.is_empty() {
+                                "already up to date"
+                            } else {
+                                &summary
+                            }),
+                            Paint::green(&synced.uploaded),
+                            Paint::red(&synced.deleted),
+                            Paint::yellow(&synced.conflicts)
+                        );
+                        print_tasks(
+                            db,
+                            local_project.as_ref(),
+                            false,
+                            force_refresh,
+                            false,
+                            &view,
+                        );
+                    }
+                    Err(e) => println!("{}", Paint::red(&format!("Error: {e}"))),
+                }
+                return;
+            }
+            if let Some(("init", init_matches)) = sub_matches.subcommand() {
+                let Some(remote) = init_matches.get_one::<String>("REMOTE") else {
+                    return;
+                };
+                if let Err(e) = sync::init(dirs.config_dir(), remote) {
+                    println!("{}", Paint::red(&format!("Error: {e}")));
+                    return;
+                }
+            }
+            match sync::sync(db, dirs.config_dir(), &operation) {
+                Ok(synced) => {
+                    let summary = journal::summary(&synced.before, &synced.after);
+                    println!(
+                        "Synced with {}: {}",
+                        Paint::yellow(&synced.remote),
+                        Paint::green(if summary.is_empty() {
+                            "already up to date"
+                        } else {
+                            &summary
+                        })
+                    );
+                    print_tasks(
+                        db,
+                        local_project.as_ref(),
+                        false,
+                        force_refresh,
+                        false,
+                        &view,
+                    );
+                }
+                Err(e) => println!("{}", Paint::red(&format!("Error: {e}"))),
+            }
+        }
+        Some(("move", sub_matches)) => {
+            let target = match sub_matches
+                .get_one::<String>("LIST")
+                .map(|l| lists::parse(l))
+            {
+                Some(Ok(target)) => target,
+                Some(Err(e)) => {
+                    println!("{}", Paint::red(&format!("Error: {e}")));
+                    return;
+                }
+                None => return,
+            };
+            let before = list_tasks(tasks_db, list);
+            let mut tasks = before.clone();
+            let Some(index) = find_task(&tasks, sub_matches.get_one::<String>("ID")) else {
+                println!(
+                    "{}",
+                    Paint::red("Error: task not found. Are you sure a task exists with that id?")
+                );
+                return;
+            };
+            if target.as_deref() == list {
+                report(&tasks[index], Paint::yellow("already in that list"));
+                return;
+            }
+            // subtasks go along with their parent, which leaves the list it was a subtask in
+            tasks[index].parent = None;
+            for i in subtasks(&tasks, tasks[index].id).into_iter().chain([index]) {
+                tasks[i].list.clone_from(&target);
+                tasks[i].touch();
+            }
+            report(
+                &tasks[index],
+                Paint::green(&format!("moved to {}", lists::name(target.as_deref()))),
+            );
+            journal::save_tasks(tasks_db, &operation, &before, &tasks);
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                false,
+                force_refresh,
+                false,
+                &view,
+            );
+        }
+        Some(("scan", sub_matches)) => {
+            let root = Path::new(
+                sub_matches
+                    .get_one::<String>("PATH")
+                    .map_or(".", String::as_str),
+            );
+            let comments = match scan::scan(root) {
+                Ok(comments) => comments,
+                Err(e) => {
+                    println!("{}", Paint::red(&format!("Error: {e}")));
+                    return;
+                }
+            };
+            let before = list_tasks(tasks_db, list);
+            let mut tasks = before.clone();
+            // tasks follow their comment as lines move, and are resolved when it is removed
+            for index in scan::update_origins(&mut tasks, &comments, root) {
+                report(
+                    &tasks[index],
+                    Paint::yellow("resolved, its comment is gone"),
+                );
+            }
+            if sub_matches.get_flag("import") {
+                let added = scan::import(&mut tasks, &comments, || next_id(tasks_db));
+                for task in tasks.iter_mut().skip(before.len()) {
+                    task.list = list.map(str::to_string);
+                }
+                journal::save_tasks(tasks_db, &operation, &before, &tasks);
+                println!("Imported {} comment(s) as tasks", Paint::green(&added));
+                print_tasks(
+                    db,
+                    local_project.as_ref(),
+                    false,
+                    force_refresh,
+                    false,
+                    &view,
+                );
+            } else {
+                journal::save_tasks(tasks_db, &operation, &before, &tasks);
+                scan::print_comments(&comments, &tasks);
+            }
         }
         _ => {
             // list all tasks with full greeting
-            print_tasks(&mut db, true, force_refresh, weather);
+            print_tasks(
+                db,
+                local_project.as_ref(),
+                true,
+                force_refresh,
+                weather,
+                &view,
+            );
         }
     }
 }
 
-fn print_tasks(db: &mut PickleDb, full_greet: bool, force_refresh: bool, just_weather: bool) {
+/// The task list of the project pls is run in, see `store::find_project`.
+struct Project {
+    // name of the project's directory
+    name: String,
+    store: Box<dyn Store>,
+}
+
+/// Which tasks `print_tasks` lists, and how much it shows about them.
+#[derive(Default)]
+struct View {
+    filter: Filter,
+    // show created/updated/completed ages
+    long: bool,
+    // the named list to show, none for the default list
+    list: Option<String>,
+    // show every list, grouped by list, instead of just `list`
+    all_lists: bool,
+}
+
+fn print_tasks(
+    db: &mut dyn Store,
+    project: Option<&Project>,
+    full_greet: bool,
+    force_refresh: bool,
+    just_weather: bool,
+    view: &View,
+) {
     println!();
     // If just weather
     if just_weather {
@@ -453,58 +1180,204 @@ fn print_tasks(db: &mut PickleDb, full_greet: bool, force_refresh: bool, just_we
             );
         }
     }
-    let tasks = get_tasks(db);
+    // the tasks of the project come under its name, after the global ones, and with every list
+    // shown, each list's tasks come under the list's name
+    let mut stores = vec![(None, get_tasks(db))];
+    if let Some(project) = project {
+        stores[0].0 = Some("global".to_string());
+        stores.push((Some(project.name.clone()), project.store.tasks()));
+    }
+    let mut groups = Vec::new();
+    for (heading, store_tasks) in stores {
+        let shown_lists = if view.all_lists {
+            lists::all(&store_tasks)
+        } else {
+            vec![view.list.clone()]
+        };
+        for list in shown_lists {
+            let name = lists::name(list.as_deref());
+            let heading = match (&heading, view.all_lists) {
+                (Some(heading), true) => Some(format!("{heading}: {name}")),
+                (None, true) => Some(name.to_string()),
+                (heading, false) => heading.clone(),
+            };
+            let in_list = lists::tasks_in(&store_tasks, list.as_deref());
+            let matching = in_list
+                .iter()
+                .filter(|t| view.filter.matches(t))
+                .cloned()
+                .collect::<Vec<_>>();
+            groups.push((heading, in_list, matching));
+        }
+    }
+    groups.retain(|(_, _, matching)| !matching.is_empty());
+    let tasks = groups
+        .iter()
+        .flat_map(|(_, _, matching)| matching)
+        .collect::<Vec<_>>();
     let total_task_count = tasks.len();
     let task_pending_count = tasks.iter().filter(|t| !t.completed).count();
     let task_completed_count = tasks.iter().filter(|t| t.completed).count();
+    // every column but the id, which the summary and "up to date" rows span
+    let span = if view.long { 7 } else { 4 };
     let mut vec = vec![];
     if total_task_count != 0 {
         vec.push(TableCell::new(""));
     }
     vec.extend(vec![TableCell::new_with_alignment(
         format!(
-            "You have {} pending tasks and {} completed tasks!",
+            "You have {} pending tasks and {} completed tasks{}!",
             Paint::red(&task_pending_count),
-            Paint::green(&task_completed_count)
+            Paint::green(&task_completed_count),
+            match &view.list {
+                Some(list) if !view.all_lists => format!(" in {}", Paint::yellow(list)),
+                _ => String::new(),
+            }
         ),
-        2,
+        span,
         Alignment::Center,
     )]);
     table.add_row(Row::new(vec));
     if total_task_count == 0 {
         table.add_row(Row::new(vec![TableCell::new_with_alignment(
-            Paint::green("Congrats! You are up to date!"),
-            2,
+            if view.filter.is_empty() {
+                Paint::green("Congrats! You are up to date!")
+            } else {
+                Paint::green("No tasks match that filter!")
+            },
+            span,
             Alignment::Center,
         )]));
     } else {
-        table.add_row(Row::new(vec![
+        let mut header = vec![
             TableCell::new_with_alignment(Paint::green("#").bold().italic(), 1, Alignment::Center),
             TableCell::new_with_alignment(
                 Paint::yellow("Title").bold().italic(),
                 1,
                 Alignment::Center,
             ),
+            TableCell::new_with_alignment(
+                Paint::yellow("Priority").bold().italic(),
+                1,
+                Alignment::Center,
+            ),
             TableCell::new_with_alignment(
                 Paint::yellow("Status").bold().italic(),
                 1,
                 Alignment::Center,
             ),
-        ]));
-        for (i, task) in tasks.iter().enumerate() {
-            table.add_row(Row::new(vec![
-                TableCell::new_with_alignment(Paint::green(&(i + 1)), 1, Alignment::Center),
-                TableCell::new_with_alignment(Paint::green(&task.title), 1, Alignment::Center),
-                TableCell::new_with_alignment(
-                    if task.completed {
-                        Paint::green("✅ | Completed!").to_string()
-                    } else {
-                        Paint::red("❌ | Uncompleted!").to_string()
-                    },
+            TableCell::new_with_alignment(
+                Paint::yellow("Due").bold().italic(),
+                1,
+                Alignment::Center,
+            ),
+        ];
+        if view.long {
+            for column in ["Created", "Updated", "Completed"] {
+                header.push(TableCell::new_with_alignment(
+                    Paint::yellow(column).bold().italic(),
                     1,
                     Alignment::Center,
-                ),
-            ]));
+                ));
+            }
+        }
+        table.add_row(Row::new(header));
+        let now = get_time();
+        for (heading, in_list, matching) in &groups {
+            if let Some(heading) = heading {
+                table.add_row(Row::new(vec![TableCell::new_with_alignment(
+                    Paint::blue(heading).bold(),
+                    span + 1,
+                    Alignment::Center,
+                )]));
+            }
+            for (depth, task) in tree_tasks(matching) {
+                // subtasks are indented below their parent
+                let title = if depth == 0 {
+                    Paint::green(&task.title).to_string()
+                } else {
+                    format!("{}└ {}", "  ".repeat(depth - 1), Paint::green(&task.title))
+                };
+                // tasks imported by `pls scan` show where their comment is
+                let title = match &task.origin {
+                    Some(origin) if origin.resolved => {
+                        format!(
+                            "{title} {}",
+                            Paint::cyan(&format!("({}, resolved)", origin.shown_path()))
+                        )
+                    }
+                    Some(origin) => {
+                        format!(
+                            "{title} {}",
+                            Paint::cyan(&format!("({}:{})", origin.shown_path(), origin.line))
+                        )
+                    }
+                    None => title,
+                };
+                // parents show how many of their subtasks are done
+                let children = subtasks(in_list, task.id);
+                let done = children.iter().filter(|&&i| in_list[i].completed).count();
+                let progress = if children.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({done}/{})", children.len())
+                };
+                let mut row = vec![
+                    TableCell::new_with_alignment(Paint::green(&task.id), 1, Alignment::Center),
+                    TableCell::new_with_alignment(
+                        if task.tags.is_empty() && task.project.is_none() {
+                            title
+                        } else {
+                            format!("{title} {}", filter::paint_tags(task))
+                        },
+                        1,
+                        Alignment::Center,
+                    ),
+                    TableCell::new_with_alignment(
+                        task.priority
+                            .map_or_else(|| "-".to_string(), Priority::paint),
+                        1,
+                        Alignment::Center,
+                    ),
+                    TableCell::new_with_alignment(
+                        if task.completed {
+                            Paint::green(&format!("✅ | Completed!{progress}")).to_string()
+                        } else if done > 0 {
+                            Paint::yellow(&format!("◐ | Partial{progress}")).to_string()
+                        } else {
+                            Paint::red(&format!("❌ | Uncompleted!{progress}")).to_string()
+                        },
+                        1,
+                        Alignment::Center,
+                    ),
+                    TableCell::new_with_alignment(
+                        match (task.due, task.recurrence) {
+                            (Some(due), Some(recurrence)) => format!(
+                                "{} {}",
+                                due::format_due(due, task.completed, now),
+                                Paint::cyan(&format!("↻ {recurrence}"))
+                            ),
+                            (Some(due), None) => due::format_due(due, task.completed, now),
+                            (None, Some(recurrence)) => {
+                                Paint::cyan(&format!("↻ {recurrence}")).to_string()
+                            }
+                            (None, None) => "-".to_string(),
+                        },
+                        1,
+                        Alignment::Center,
+                    ),
+                ];
+                if view.long {
+                    for timestamp in [task.created_at, task.updated_at, task.completed_at] {
+                        row.push(TableCell::new_with_alignment(
+                            timestamp.map_or_else(|| "-".to_string(), |t| due::format_age(t, now)),
+                            1,
+                            Alignment::Center,
+                        ));
+                    }
+                }
+                table.add_row(Row::new(row));
+            }
         }
     }
 
@@ -513,34 +1386,529 @@ fn print_tasks(db: &mut PickleDb, full_greet: bool, force_refresh: bool, just_we
 
 #[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
 struct Task {
+    // stable id, shown in the task table and used to address the task
+    #[serde(default)]
+    id: u64,
     title: String,
     completed: bool,
+    // unix timestamp of when the task is due
+    #[serde(default)]
+    due: Option<i64>,
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    project: Option<String>,
+    // recurring tasks stay pending and move to their next due date when marked as done
+    #[serde(default)]
+    recurrence: Option<Recurrence>,
+    // id of the task this is a subtask of
+    #[serde(default)]
+    parent: Option<u64>,
+    // unix timestamps, missing on tasks saved before they were recorded
+    #[serde(default)]
+    created_at: Option<i64>,
+    #[serde(default)]
+    completed_at: Option<i64>,
+    #[serde(default)]
+    updated_at: Option<i64>,
+    // words from imported todo.txt lines that pls doesn't understand, such as `t:` or `rec:`
+    // key/values, written back out on export
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra: Vec<String>,
+    // UID of the calendar VTODO or Taskwarrior task the task was imported from
+    #[serde(default)]
+    uid: Option<String>,
+    // notes on the task, imported from Taskwarrior annotations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    notes: Vec<Note>,
+    // name of the list the task is in, none for the default list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    list: Option<String>,
+    // the TODO comment the task was imported from by `pls scan --import`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<scan::Origin>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+struct Note {
+    text: String,
+    // unix timestamp
+    #[serde(default)]
+    created_at: Option<i64>,
 }
 
 impl Task {
     fn new(title: &String) -> Self {
+        let now = get_time().unix_timestamp();
         Self {
             title: title.to_string(),
+            created_at: Some(now),
+            updated_at: Some(now),
             ..Default::default()
         }
     }
-    fn new_completed(title: &String) -> Self {
+    fn make_complete(&self) -> Self {
+        let now = get_time().unix_timestamp();
         Self {
-            title: title.to_string(),
             completed: true,
+            completed_at: Some(now),
+            updated_at: Some(now),
+            ..self.clone()
         }
     }
-    fn make_complete(&self) -> Self {
-        Self::new_completed(&self.title)
+    fn make_incomplete(&self) -> Self {
+        Self {
+            completed: false,
+            completed_at: None,
+            updated_at: Some(get_time().unix_timestamp()),
+            ..self.clone()
+        }
+    }
+    fn touch(&mut self) {
+        self.updated_at = Some(get_time().unix_timestamp());
+    }
+}
+
+// mark a recurring task as done: a completed copy is added to the list to log the completion,
+// and the task itself stays pending with its next due date
+fn complete_recurring(db: &mut dyn Store, tasks: &mut Vec<Task>, index: usize) {
+    let Some(recurrence) = tasks[index].recurrence else {
+        return;
+    };
+    let now = get_time();
+    let mut log = tasks[index].make_complete();
+    log.id = next_id(db);
+    log.recurrence = None;
+    let due = tasks[index]
+        .due
+        .and_then(|due| OffsetDateTime::from_unix_timestamp(due).ok())
+        .map_or(now, |due| due.to_offset(now.offset()));
+    tasks[index].due = Some(recurrence.next_due(due, now).unix_timestamp());
+    tasks[index].touch();
+    tasks.push(log);
+}
+
+// order tasks for display: pending tasks by priority, then due date, then the order they were
+// added in, followed by completed tasks in the order they were added in
+fn sort_tasks<'a>(tasks: impl IntoIterator<Item = &'a Task>) -> Vec<&'a Task> {
+    let (mut pending, completed): (Vec<&Task>, Vec<&Task>) =
+        tasks.into_iter().partition(|t| !t.completed);
+    pending.sort_by_key(|t| (t.priority.is_none(), t.priority, t.due.is_none(), t.due));
+    pending.extend(completed);
+    pending
+}
+
+// order tasks for display with each subtask right below its parent, along with how deeply
+// nested it is. subtasks whose parent isn't in the list are shown at the top level, and so is
+// the first task of parents that point at each other, so that no task goes missing
+fn tree_tasks(tasks: &[Task]) -> Vec<(usize, &Task)> {
+    fn visit<'a>(
+        tasks: &'a [Task],
+        task: &'a Task,
+        depth: usize,
+        out: &mut Vec<(usize, &'a Task)>,
+    ) {
+        if out.iter().any(|(_, t)| std::ptr::eq(*t, task)) {
+            return;
+        }
+        out.push((depth, task));
+        for child in sort_tasks(tasks.iter().filter(|t| t.parent == Some(task.id))) {
+            visit(tasks, child, depth + 1, out);
+        }
+    }
+    let roots = tasks.iter().filter(|t| {
+        t.parent
+            .is_none_or(|parent| !tasks.iter().any(|p| p.id == parent))
+    });
+    let mut out = Vec::new();
+    for task in sort_tasks(roots).into_iter().chain(sort_tasks(tasks)) {
+        visit(tasks, task, 0, &mut out);
     }
+    out
+}
+
+// positions of every subtask below a task, including subtasks of subtasks
+fn subtasks(tasks: &[Task], id: u64) -> Vec<usize> {
+    let mut found = Vec::new();
+    let mut parents = vec![id];
+    while let Some(parent) = parents.pop() {
+        for (index, task) in tasks.iter().enumerate() {
+            if task.parent == Some(parent) && task.id != id && !found.contains(&index) {
+                found.push(index);
+                parents.push(task.id);
+            }
+        }
+    }
+    found
+}
+
+fn open_project(
+    path: &Path,
+    config_dir: &Path,
+    key: Option<&crypto::Key>,
+) -> Result<Project, String> {
+    let mut store: Box<dyn Store> = Box::new(store::ProjectStore::open(path, config_dir, key)?);
+    migrate::migrate(store.as_mut(), &config_dir.join("backups"))?;
+    // the directory the .pls.json file or .pls directory is in
+    let dir = if path.ends_with(".pls.json") {
+        path.parent()
+    } else {
+        path.parent().and_then(Path::parent)
+    };
+    let name = dir.and_then(Path::file_name).map_or_else(
+        || "project".to_string(),
+        |name| name.to_string_lossy().to_string(),
+    );
+    Ok(Project { name, store })
+}
+
+// the subcommands and options, built apart from main so that the builder doesn't take up
+// main's stack
+fn cli() -> Command {
+    clap::Command::new("pls").version("0.1.0")
+        .propagate_version(true)
+        .subcommand_required(false)
+        .arg_required_else_help(false)
+        .subcommand(
+            Command::new("add")
+                .about("Add task to todo")
+                .arg(arg!([NAME]))
+                .arg(arg!(--due <DUE> "When the task is due, ex: \"tomorrow\", \"in 3 days\", \"next friday 5pm\", \"2024-05-17\""))
+                .arg(arg!(-p --priority <PRIORITY> "Priority of the task: high, med or low (or !!!, !! or ! in the name)"))
+                .arg(arg!(--under <ID> "Add the task as a subtask of another task"))
+                .arg(arg!(--every <RECURRENCE> "Make the task recurring: daily, weekday, weekly:<day> (ex: weekly:mon) or monthly:<day> (ex: monthly:1)")),
+        )
+        .subcommand(
+            Command::new("do")
+                .alias("done")
+                .about("Mark task as done")
+                .arg(arg!([SELECTOR] ... "Task ids, ranges (4-6), \"last\", or +tags and @projects, ex: 2,4-6")),
+        )
+        .subcommand(
+            Command::new("undo").alias("undone")
+                .about("Mark task as undone")
+                .arg(arg!([SELECTOR] ... "Task ids, ranges (4-6), \"last\", or +tags and @projects, ex: 2,4-6")),
+        )
+        .subcommand(
+            Command::new("rm")
+                .alias("remove")
+                .alias("del")
+                .alias("delete")
+                .about("Remove task")
+                .arg(arg!([SELECTOR] ... "Task ids, ranges (4-6), \"last\", or +tags and @projects, ex: 2,4-6")),
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("Rename a task, or edit all of its fields in $EDITOR if no title is given")
+                .arg(arg!(<ID> "Task id"))
+                .arg(arg!([TITLE] "New title of the task")),
+        )
+        .subcommand(
+            Command::new("prio")
+                .alias("priority")
+                .about("Change the priority of a task")
+                .arg(arg!(<ID> "Task id"))
+                .arg(arg!(<PRIORITY> "high, med, low or none")),
+        )
+        .subcommand(
+            Command::new("list")
+                .alias("ls")
+                .alias("all")
+                .about("List tasks")
+                .arg(arg!([FILTER] ... "Only list tasks with these +tags and @projects"))
+                .arg(arg!(-l --long "Also show when tasks were created, updated and completed"))
+                .arg(arg!(--"all-lists" "List the tasks of every list, grouped by list")),
+        )
+        .subcommand(Command::new("lists").about("List the task lists, with how many tasks each has"))
+        .subcommand(
+            Command::new("scan")
+                .about("Find TODO, FIXME and HACK comments in the files under a directory, skipping files in .gitignore")
+                .arg(arg!([PATH] "Directory to scan, the current directory if not given"))
+                .arg(arg!(--import "Add a task for each comment that isn't a task yet")),
+        )
+        .subcommand(
+            Command::new("link")
+                .about("Keep the tasks in a Markdown checklist (- [ ] lines) or an Org file (TODO headlines) instead. Run without a file to show the linked one")
+                .arg(arg!([FILE] "File to keep the tasks in, created if it doesn't exist")),
+        )
+        .subcommand(sync_command())
+        .subcommand(Command::new("unlink").about("Stop using the linked file, and go back to the tasks pls keeps itself"))
+        .subcommand(
+            Command::new("move")
+                .alias("mv")
+                .about("Move a task, along with its subtasks, to another list")
+                .arg(arg!(<ID> "Task id"))
+                .arg(arg!(<LIST> "List to move the task to, \"default\" for the default list")),
+        )
+        .subcommand(
+            Command::new("install")
+                .about("Install into shell. \"fish\", \"bash\", or \"zsh\" as options are supported. 
+                Alternatively, enter \"weather\" as an option to install a crontab service to automatically update the weather in the background.")
+                .arg(arg!([SHELL])),
+        )
+        .subcommand(Command::new("clean").about("Clean all completed tasks"))
+        .subcommand(Command::new("history").about("List recent changes to the task list"))
+        .subcommand(
+            Command::new("revert")
+                .about("Revert the last changes to the task list")
+                .arg(arg!([COUNT] "Number of changes to revert").value_parser(clap::value_parser!(usize))),
+        )
+        .subcommand(
+            Command::new("redo")
+                .about("Redo changes undone by revert")
+                .arg(arg!([COUNT] "Number of changes to redo").value_parser(clap::value_parser!(usize))),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Print all tasks in another format, or write them to a file")
+                .arg(arg!(-f --format <FORMAT> "Format to export to, guessed from the file extension if not given (default todotxt)").value_parser(["todotxt", "json", "csv", "markdown", "ics", "taskwarrior"]))
+                .arg(arg!(-o --output <FILE> "File to write to instead of printing the tasks")),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Add tasks from a file, skipping tasks that are already in the list. Tasks imported from a calendar before are updated")
+                .arg(arg!(<FILE> "File to import"))
+                .arg(arg!(-f --format <FORMAT> "Format of the file, guessed from its extension if not given").value_parser(["todotxt", "json", "csv", "ics", "taskwarrior"])),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Merge the tasks of a copy of pls.json, such as one from another machine, task by task")
+                .arg(arg!(<FILE> "pls.json file to merge"))
+                .arg(arg!(-s --strategy <STRATEGY> "Version kept of a task changed on both sides: ours, theirs or the one changed last (default newest)").value_parser(["ours", "theirs", "newest"])),
+        )
+        .subcommands(encryption_subcommands())
+        .subcommand(
+            Command::new("config")
+                .about("Show or change settings. Run without arguments to list all settings, or give an empty value to reset one")
+                .arg(arg!([KEY]))
+                .arg(arg!([VALUE])),
+        )
+        .arg(
+            arg!(
+                -r --refresh "Force refresh of weather"
+            )
+            .action(ArgAction::SetTrue),
+        ).arg(
+            arg!(
+                -a --all "Apply change to all tasks"
+              )
+            .action(ArgAction::SetTrue),
+        ).arg(
+            arg!(
+                -w --weather "Just print the weather!"
+            ).action(ArgAction::SetTrue)
+        ).arg(
+            arg!(
+                --list <LIST> "Use a named list instead of the default list, ex: --list work"
+            ).global(true)
+        ).arg(
+            arg!(
+                --local "Use the tasks of the project in this directory (.pls.json or .pls/), which \
+                `add --local` creates if there is none"
+            ).global(true)
+        )
+}
+
+// `pls sync` and its subcommands, apart from cli() for the same reason
+fn sync_command() -> Command {
+    Command::new("sync")
+        .about("Commit the tasks, pull the changes made on other machines and push them back, merging them task by task")
+        .subcommand(
+            Command::new("init")
+                .about("Keep the tasks in a git repository in the config directory, synced with a remote, then sync")
+                .arg(arg!(<REMOTE> "Git URL or path of the repository to sync with, ex: a bare repository")),
+        )
+        .subcommand(
+            Command::new("caldav")
+                .about("Sync the tasks with a CalDAV collection instead, ex: a Nextcloud task list. The password is read from PLS_CALDAV_PASSWORD or the caldav-password setting")
+                .arg(arg!(--url <URL> "URL of the collection, saved for the next syncs"))
+                .arg(arg!(--user <USER> "User name to log in with, saved for the next syncs")),
+        )
+}
+
+// `pls encrypt` and the commands that go with it, apart from cli() too
+fn encryption_subcommands() -> [Command; 5] {
+    [
+        Command::new("encrypt")
+            .about("Encrypt pls.json with a passphrase, or change the passphrase it is encrypted with. The key is kept for 15 minutes (or PLS_KEY_MINUTES) after the passphrase is typed in"),
+        Command::new("decrypt").about("Decrypt pls.json, keeping it in plain text again"),
+        Command::new("unlock").about("Print the key of pls.json as a shell command that sets PLS_KEY, ex: eval \"$(pls unlock)\""),
+        Command::new("lock").about("Forget the key that was kept after the passphrase was typed in"),
+        Command::new("agent")
+            .hide(true)
+            .about("Keep the key read from stdin for other pls processes")
+            .arg(arg!(<SOCKET> "Socket to listen on")),
+    ]
+}
+
+// run one of the encryption subcommands on pls.json in the config directory
+fn encryption_command(name: &str, sub_matches: &ArgMatches, dir: &Path) -> Result<(), String> {
+    let path = dir.join("pls.json");
+    match name {
+        "encrypt" => {
+            if sync::is_set_up(dir) {
+                return Err(format!(
+                    "{} is synced in plain text, remove {} to stop syncing before encrypting. \
+                     The tasks already pushed stay in the history of the remote",
+                    dir.join("tasks.json").display(),
+                    dir.join(".git").display()
+                ));
+            }
+            let mut store = store::open_pls_json(dir)?;
+            let key = crypto::Key::generate(&crypto::new_passphrase()?)?;
+            let old = store.key().cloned();
+            store.set_key(Some(key.clone()))?;
+            store::encrypt_copies(dir, old.as_ref(), Some(&key))?;
+            crypto::start_agent(&key, &path);
+            println!(
+                "{}",
+                Paint::green(&format!(
+                    "Encrypted {}. Your tasks can't be recovered without the passphrase, keep it safe",
+                    path.display()
+                ))
+            );
+        }
+        "decrypt" => {
+            let mut store = store::open_pls_json(dir)?;
+            if store.key().is_none() {
+                return Err(format!("{} isn't encrypted", path.display()));
+            }
+            let old = store.key().cloned();
+            store.set_key(None)?;
+            store::encrypt_copies(dir, old.as_ref(), None)?;
+            crypto::lock(&path);
+            println!("{}", Paint::green(&format!("Decrypted {}", path.display())));
+        }
+        "unlock" => {
+            let store = store::open_pls_json(dir)?;
+            let key = store
+                .key()
+                .ok_or_else(|| format!("{} isn't encrypted", path.display()))?;
+            println!("export {}={}", crypto::KEY_VAR, key.to_hex());
+        }
+        "lock" => {
+            if crypto::lock(&path) {
+                println!("{}", Paint::green("Forgot the key"));
+            } else {
+                println!("No key was kept");
+            }
+            if std::env::var(crypto::KEY_VAR).is_ok() {
+                println!(
+                    "{}",
+                    Paint::yellow(&format!(
+                        "{0} is still set, run `unset {0}` to forget it too",
+                        crypto::KEY_VAR
+                    ))
+                );
+            }
+        }
+        _ => {
+            if let Some(socket) = sub_matches.get_one::<String>("SOCKET") {
+                crypto::agent(Path::new(socket))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// merge the tasks of another store into ours, reporting what changed
+fn merge_tasks(
+    db: &mut dyn Store,
+    other: &dyn Store,
+    strategy: merge::Strategy,
+    operation: &str,
+) -> merge::Merged {
+    let before = get_tasks(db);
+    let their_tasks = get_tasks(other);
+    let (our_tombstones, their_tombstones) = (merge::tombstones(db), merge::tombstones(other));
+    let ours = merge::Side {
+        tasks: &before,
+        tombstones: &our_tombstones,
+    };
+    // the ids given by merges here, and by merges there to tasks of ours
+    let mut renumbered = merge::renumbered(db);
+    renumbered.extend(merge::renumbered_there(other, &ours));
+    let merged = merge::merge(
+        &merge::base(db),
+        &renumbered,
+        &ours,
+        &merge::Side {
+            tasks: &their_tasks,
+            tombstones: &their_tombstones,
+        },
+        strategy,
+    );
+    for task in &merged.added {
+        report(task, Paint::green("added"));
+    }
+    for task in &merged.updated {
+        report(task, Paint::green("updated"));
+    }
+    for task in &merged.removed {
+        report(task, Paint::red("removed"));
+    }
+    for conflict in &merged.conflicts {
+        let outcome = format!(
+            "conflict, {}: kept {}",
+            conflict.reason,
+            if conflict.theirs { "theirs" } else { "ours" }
+        );
+        report(&conflict.task, Paint::yellow(outcome.as_str()));
+    }
+    journal::save_tasks(db, operation, &before, &merged.tasks);
+    merge::save(db, &merged, &their_tasks, &renumbered).expect("Failed to save merge");
+    merged
+}
+
+fn get_tasks(db: &dyn Store) -> Vec<Task> {
+    db.tasks()
+}
+
+// the tasks of one list, none being the default list
+fn list_tasks(db: &dyn Store, list: Option<&str>) -> Vec<Task> {
+    lists::tasks_in(&get_tasks(db), list)
+}
+
+// ids come from a monotonic counter so they are never reused, even after a task is removed
+fn next_id(db: &mut dyn Store) -> u64 {
+    // read and bumped in one transaction, so that two pls processes never hand out the same id
+    db.begin().expect("Failed to start transaction");
+    let id = db.get::<u64>("task-id").unwrap_or(1);
+    db.set("task-id", &(id + 1)).expect("Failed to set task id");
+    db.commit().expect("Failed to set task id");
+    id
+}
+
+// selectors can be given as several arguments, ex: `pls do 2 4-6 +work`
+fn get_selector(sub_matches: &ArgMatches) -> Vec<String> {
+    sub_matches
+        .get_many::<String>("SELECTOR")
+        .unwrap_or_default()
+        .cloned()
+        .collect()
 }
 
-fn make_complete(task: &Task) -> Task {
-    task.make_complete()
+// print the outcome of a command for one task
+fn report(task: &Task, outcome: Painted<&str>) {
+    println!(
+        "Task {} ({}): {}",
+        Paint::yellow(&task.id),
+        task.title,
+        outcome
+    );
 }
 
-fn get_tasks(db: &PickleDb) -> Vec<Task> {
-    db.get::<Vec<Task>>("tasks").unwrap_or_default()
+/// Find a task by its id. An id no task has finds nothing, rather than the task at that
+/// position, so that a stale id never acts on another task.
+/// If no id is given, the first task is used.
+fn find_task(tasks: &[Task], id: Option<&String>) -> Option<usize> {
+    let Some(id) = id else {
+        return (!tasks.is_empty()).then_some(0);
+    };
+    let id = id.trim().parse::<u64>().ok()?;
+    tasks.iter().position(|t| t.id == id)
 }
 
 fn create_dir() {
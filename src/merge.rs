@@ -0,0 +1,422 @@
+use std::{collections::HashMap, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{store::Store, Task};
+
+/// Which version of a task is kept when it was changed on both sides of a merge, or changed on
+/// one side and removed on the other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Strategy {
+    Ours,
+    Theirs,
+    // the side that changed it last, ours if both did at the same time
+    #[default]
+    Newest,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "ours" => Ok(Self::Ours),
+            "theirs" => Ok(Self::Theirs),
+            "newest" => Ok(Self::Newest),
+            _ => Err(format!(
+                "Unknown strategy \"{s}\", must be ours, theirs or newest"
+            )),
+        }
+    }
+}
+
+/// What is left of a removed task, so that merging a copy of the tasks that still has it
+/// doesn't bring it back.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Tombstone {
+    pub id: u64,
+    pub title: String,
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    // unix timestamp
+    pub removed_at: i64,
+}
+
+impl Tombstone {
+    pub fn new(task: &Task, removed_at: i64) -> Self {
+        Self {
+            id: task.id,
+            title: task.title.clone(),
+            created_at: task.created_at,
+            removed_at,
+        }
+    }
+
+    fn buries(&self, task: &Task) -> bool {
+        self.id == task.id
+            && match (self.created_at, task.created_at) {
+                (Some(a), Some(b)) => a == b,
+                _ => self.title == task.title,
+            }
+    }
+
+    fn is_duplicate(&self, other: &Self) -> bool {
+        self.id == other.id && self.created_at == other.created_at
+    }
+}
+
+/// A task of theirs that got a new id in a merge, because one of ours had its id, so that the
+/// next merge takes it for the same task rather than adding it again.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Renumbered {
+    pub id: u64,
+    pub title: String,
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    // the id it has here
+    pub new_id: u64,
+}
+
+impl Renumbered {
+    // whether it's the task with this id, created at and title on their side
+    fn is_of(&self, id: u64, created_at: Option<i64>, title: &str) -> bool {
+        self.id == id
+            && match (self.created_at, created_at) {
+                (Some(a), Some(b)) => a == b,
+                _ => self.title == title,
+            }
+    }
+}
+
+/// One of the two task lists being merged, with the tasks removed from it.
+pub struct Side<'a> {
+    pub tasks: &'a [Task],
+    pub tombstones: &'a [Tombstone],
+}
+
+/// A task changed on one side and changed or removed on the other.
+pub struct Conflict {
+    pub task: Task,
+    pub reason: &'static str,
+    // whether their version was kept
+    pub theirs: bool,
+}
+
+/// The outcome of a merge, with what it changed on our side.
+#[derive(Default)]
+pub struct Merged {
+    pub tasks: Vec<Task>,
+    // the tombstones of both sides
+    pub tombstones: Vec<Tombstone>,
+    pub added: Vec<Task>,
+    pub updated: Vec<Task>,
+    pub removed: Vec<Task>,
+    pub conflicts: Vec<Conflict>,
+    // their tasks given a new id by this merge
+    pub renumbered: Vec<Renumbered>,
+}
+
+/// Merge their changes into our tasks, task by task, since `base`, the tasks both sides had
+/// when they last merged (empty if they never did). A task changed on one side only takes that
+/// change, and without a base, the version updated last is kept. A task changed on both sides
+/// is a conflict, resolved by `strategy`. A task removed on one side is removed on the other,
+/// unless it was changed after it was removed, which is a conflict too. Tasks added on both
+/// sides with the same id are both kept, theirs getting a new id, and `renumbered` has the ids
+/// their tasks got in earlier merges.
+pub fn merge(
+    base: &[Task],
+    renumbered: &[Renumbered],
+    ours: &Side<'_>,
+    theirs: &Side<'_>,
+    strategy: Strategy,
+) -> Merged {
+    let version = |tasks: &[Task], id: u64| tasks.iter().find(|t| t.id == id).cloned();
+    let our_tombstones = live(ours);
+    // their tasks whose id is taken by another one of ours
+    let (their_tasks, mut clashes) = renumber(theirs.tasks, renumbered);
+    let their_tombstones = live(theirs)
+        .into_iter()
+        .map(|mut tomb| {
+            if let Some(r) = renumbered
+                .iter()
+                .find(|r| r.is_of(tomb.id, tomb.created_at, &tomb.title))
+            {
+                tomb.id = r.new_id;
+            }
+            tomb
+        })
+        .collect::<Vec<_>>();
+    let theirs = &Side {
+        tasks: &their_tasks,
+        tombstones: &their_tombstones,
+    };
+    let mut merged = Merged::default();
+    for o in ours.tasks {
+        let b = version(base, o.id);
+        let Some(t) = version(theirs.tasks, o.id) else {
+            match their_tombstones.iter().find(|tomb| tomb.buries(o)) {
+                Some(tomb)
+                    if b.as_ref() != Some(o)
+                        && o.updated_at.unwrap_or_default() > tomb.removed_at =>
+                {
+                    let theirs = strategy == Strategy::Theirs;
+                    merged.conflicts.push(Conflict {
+                        task: o.clone(),
+                        reason: "changed here, removed there",
+                        theirs,
+                    });
+                    if theirs {
+                        merged.removed.push(o.clone());
+                    } else {
+                        merged.tasks.push(o.clone());
+                    }
+                }
+                Some(_) => merged.removed.push(o.clone()),
+                None => merged.tasks.push(o.clone()),
+            }
+            continue;
+        };
+        if t == *o {
+            merged.tasks.push(t);
+        } else if !is_same_task(o, &t, b.as_ref()) {
+            merged.tasks.push(o.clone());
+            clashes.push(t);
+        } else if b.as_ref() == Some(o) || b.is_none() && t.updated_at > o.updated_at {
+            merged.updated.push(t.clone());
+            merged.tasks.push(t);
+        } else if b.as_ref() == Some(&t) || b.is_none() && t.updated_at < o.updated_at {
+            merged.tasks.push(o.clone());
+        } else {
+            let theirs = match strategy {
+                Strategy::Ours => false,
+                Strategy::Theirs => true,
+                Strategy::Newest => t.updated_at > o.updated_at,
+            };
+            let kept = if theirs { t } else { o.clone() };
+            merged.conflicts.push(Conflict {
+                task: kept.clone(),
+                reason: "changed on both sides",
+                theirs,
+            });
+            merged.tasks.push(kept);
+        }
+    }
+    for t in theirs
+        .tasks
+        .iter()
+        .filter(|t| !ours.tasks.iter().any(|o| o.id == t.id))
+    {
+        let b = version(base, t.id);
+        match our_tombstones.iter().find(|tomb| tomb.buries(t)) {
+            Some(tomb)
+                if b.as_ref() != Some(t) && t.updated_at.unwrap_or_default() > tomb.removed_at =>
+            {
+                let theirs = strategy != Strategy::Ours;
+                merged.conflicts.push(Conflict {
+                    task: t.clone(),
+                    reason: "removed here, changed there",
+                    theirs,
+                });
+                if theirs {
+                    merged.added.push(t.clone());
+                }
+            }
+            Some(_) => {}
+            None => merged.added.push(t.clone()),
+        }
+    }
+
+    // clashing tasks get ids after every other one, and their subtasks follow them
+    let last_id = merged
+        .tasks
+        .iter()
+        .chain(&merged.added)
+        .chain(&clashes)
+        .map(|t| t.id)
+        .chain(our_tombstones.iter().chain(&their_tombstones).map(|t| t.id))
+        .max()
+        .unwrap_or_default();
+    let mut new_ids = HashMap::new();
+    for (id, task) in (last_id + 1..).zip(&mut clashes) {
+        new_ids.insert(task.id, id);
+        merged.renumbered.push(Renumbered {
+            id: task.id,
+            title: task.title.clone(),
+            created_at: task.created_at,
+            new_id: id,
+        });
+        task.id = id;
+    }
+    merged.added.extend(clashes);
+    for task in &mut merged.added {
+        if let Some(parent) = task.parent.and_then(|p| new_ids.get(&p)) {
+            task.parent = Some(*parent);
+        }
+    }
+    merged.tasks.extend(merged.added.iter().cloned());
+    merged.tombstones = combine(
+        our_tombstones.into_iter().chain(their_tombstones),
+        &merged.tasks,
+    );
+    merged
+}
+
+/// Tombstones for the tasks in `base` that are no longer in `tasks`, removed when they were
+/// last updated, for merging copies that don't keep tombstones themselves.
+pub fn removed(base: &[Task], tasks: &[Task]) -> Vec<Tombstone> {
+    base.iter()
+        .filter(|b| !tasks.iter().any(|t| t.id == b.id))
+        .map(|b| Tombstone::new(b, b.updated_at.unwrap_or_default()))
+        .collect()
+}
+
+/// Save what a `pls merge` leaves besides the tasks: the tombstones of both sides, the ids it
+/// gave their tasks, and the tasks both sides now have, which are the base of the next merge.
+/// `renumbered` is what the merge was given.
+pub fn save(
+    db: &mut dyn Store,
+    merged: &Merged,
+    theirs: &[Task],
+    renumbered: &[Renumbered],
+) -> Result<(), String> {
+    let mut ids = renumbered.to_vec();
+    ids.extend(merged.renumbered.iter().cloned());
+    // with the ids they have here. tasks where ours won aren't on their side yet
+    let (theirs, _) = renumber(theirs, &ids);
+    let mut given = self::renumbered(db);
+    given.extend(merged.renumbered.iter().cloned());
+    let common = merged
+        .tasks
+        .iter()
+        .filter(|t| theirs.contains(t))
+        .cloned()
+        .collect::<Vec<_>>();
+    db.begin()?;
+    let saved = bury(db, &merged.tombstones)
+        .and_then(|()| db.set("merge-base", &common))
+        .and_then(|()| db.set("merge-ids", &given))
+        .and_then(|()| skip_ids(db, &merged.tasks));
+    let committed = db.commit();
+    saved.and(committed)
+}
+
+/// Move the id counter past the ids of merged tasks, so that ids given on the other side aren't
+/// given again.
+pub fn skip_ids(db: &mut dyn Store, tasks: &[Task]) -> Result<(), String> {
+    let next = tasks.iter().map(|t| t.id + 1).max().unwrap_or(1);
+    if db.get::<u64>("task-id").unwrap_or(1) < next {
+        db.set("task-id", &next)?;
+    }
+    Ok(())
+}
+
+/// The tasks both sides had after the last `pls merge`.
+pub fn base(db: &dyn Store) -> Vec<Task> {
+    db.get("merge-base").unwrap_or_default()
+}
+
+/// Their tasks given a new id by a `pls merge`.
+pub fn renumbered(db: &dyn Store) -> Vec<Renumbered> {
+    db.get("merge-ids").unwrap_or_default()
+}
+
+/// Our tasks given a new id by a `pls merge` on their side, which `theirs` keeps, turned into
+/// their tasks with the id they have here, so that merging back takes them for ours.
+pub fn renumbered_there(theirs: &dyn Store, ours: &Side<'_>) -> Vec<Renumbered> {
+    renumbered(theirs)
+        .into_iter()
+        .filter(|r| {
+            ours.tasks
+                .iter()
+                .any(|t| r.is_of(t.id, t.created_at, &t.title))
+                || ours
+                    .tombstones
+                    .iter()
+                    .any(|tomb| r.is_of(tomb.id, tomb.created_at, &tomb.title))
+        })
+        .map(|r| Renumbered {
+            id: r.new_id,
+            new_id: r.id,
+            ..r
+        })
+        .collect()
+}
+
+/// The tombstones of the tasks removed from the store.
+pub fn tombstones(db: &dyn Store) -> Vec<Tombstone> {
+    db.get("tombstones").unwrap_or_default()
+}
+
+/// Add tombstones to the store, dropping the ones of tasks that are back, ex: after a revert.
+pub fn bury(db: &mut dyn Store, tombstones: &[Tombstone]) -> Result<(), String> {
+    let buried = combine(
+        self::tombstones(db)
+            .into_iter()
+            .chain(tombstones.iter().cloned()),
+        &db.tasks(),
+    );
+    db.set("tombstones", &buried)
+}
+
+// the tombstones without duplicates, or the ones of tasks that are there
+fn combine(tombstones: impl Iterator<Item = Tombstone>, tasks: &[Task]) -> Vec<Tombstone> {
+    let mut combined: Vec<Tombstone> = Vec::new();
+    for tombstone in tombstones {
+        if !combined.iter().any(|c| c.is_duplicate(&tombstone))
+            && !tasks.iter().any(|t| tombstone.buries(t))
+        {
+            combined.push(tombstone);
+        }
+    }
+    combined
+}
+
+// their tasks with the ids they got in earlier merges, and the ones whose id one of those has now
+fn renumber(tasks: &[Task], renumbered: &[Renumbered]) -> (Vec<Task>, Vec<Task>) {
+    let new_id = |task: &Task| {
+        renumbered
+            .iter()
+            .find(|r| r.is_of(task.id, task.created_at, &task.title))
+            .map(|r| r.new_id)
+    };
+    let new_ids = tasks
+        .iter()
+        .filter_map(|t| Some((t.id, new_id(t)?)))
+        .collect::<HashMap<_, _>>();
+    let (mut kept, mut clashes) = (Vec::new(), Vec::new());
+    for task in tasks {
+        let mut task = task.clone();
+        task.parent = task.parent.map(|p| new_ids.get(&p).copied().unwrap_or(p));
+        if let Some(id) = new_id(&task) {
+            task.id = id;
+            kept.push(task);
+        } else if new_ids.values().any(|&id| id == task.id) {
+            clashes.push(task);
+        } else {
+            kept.push(task);
+        }
+    }
+    (kept, clashes)
+}
+
+// the tombstones of a side that don't belong to a task it has again
+fn live(side: &Side<'_>) -> Vec<Tombstone> {
+    side.tombstones
+        .iter()
+        .filter(|tomb| !side.tasks.iter().any(|t| tomb.buries(t)))
+        .cloned()
+        .collect()
+}
+
+// whether our and their task with the same id are the same task, rather than two tasks added
+// on each side. without a version both sides had, their titles have to match too
+fn is_same_task(ours: &Task, theirs: &Task, base: Option<&Task>) -> bool {
+    let created_together = |a: &Task| match (a.created_at, theirs.created_at) {
+        (Some(a_created), Some(their_created)) => a_created == their_created,
+        _ => true,
+    };
+    base.map_or_else(
+        || created_together(ours) && ours.title == theirs.title,
+        created_together,
+    )
+}
@@ -0,0 +1,119 @@
+use std::{
+    fs::{DirBuilder, OpenOptions},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    get_time, next_id,
+    store::{write_copy, Store},
+};
+
+/// Layout of the database written by this version of pls. Databases without a
+/// `schema_version` key are from 0.2.0, which is version 1.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// A step that upgrades the database from one schema version to the next.
+struct Migration {
+    from: u32,
+    migrate: fn(&mut dyn Store) -> Result<(), String>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    // 0.2.0 tasks are only a title and a completed flag. every field added since has a default,
+    // except for the id, which has to be unique
+    Migration {
+        from: 1,
+        migrate: |db| {
+            let mut tasks = db.tasks();
+            for task in tasks.iter_mut().filter(|t| t.id == 0) {
+                task.id = next_id(db);
+            }
+            db.set_tasks(&tasks)
+        },
+    },
+];
+
+/// What `migrate` did to bring the database up to date.
+pub struct Upgrade {
+    pub from: u32,
+    pub backup: PathBuf,
+}
+
+/// Upgrade the database to `SCHEMA_VERSION`, one migration at a time, after saving a backup of
+/// it in `backup_dir`. Returns `None` if it was already up to date.
+pub fn migrate(db: &mut dyn Store, backup_dir: &Path) -> Result<Option<Upgrade>, String> {
+    // a new database starts out at the current version
+    if db.keys().is_empty() {
+        db.set("schema_version", &SCHEMA_VERSION)?;
+        return Ok(None);
+    }
+    let from = db.get::<u32>("schema_version").unwrap_or(1);
+    if from > SCHEMA_VERSION {
+        return Err(format!(
+            "the database has schema version {from}, but this version of pls only understands \
+             up to {SCHEMA_VERSION}, please update pls"
+        ));
+    }
+    if from == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    let backup = backup(db, backup_dir, from)?;
+    db.begin()?;
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= from) {
+        (migration.migrate)(db).map_err(|e| {
+            format!(
+                "Failed to upgrade the database from version {}: {e}, a backup is at {}",
+                migration.from,
+                backup.display()
+            )
+        })?;
+        db.set("schema_version", &(migration.from + 1))?;
+    }
+    db.commit()?;
+    Ok(Some(Upgrade { from, backup }))
+}
+
+/// Upgrade a copy of a database that is only read, such as one from another machine, in memory
+/// and without a backup.
+pub fn upgrade(db: &mut dyn Store) -> Result<(), String> {
+    let from = db.get::<u32>("schema_version").unwrap_or(1);
+    if from > SCHEMA_VERSION {
+        return Err(format!(
+            "it has schema version {from}, but this version of pls only understands up to \
+             {SCHEMA_VERSION}, please update pls"
+        ));
+    }
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= from) {
+        (migration.migrate)(db)?;
+        db.set("schema_version", &(migration.from + 1))?;
+    }
+    Ok(())
+}
+
+// save everything in the database to a new json file, ex: backups/pls-v1-1715940000.json,
+// which can be copied over pls.json to restore it. a backup made in the same second as another
+// gets a counter, ex: pls-v1-1715940000-2.json, rather than overwriting it
+fn backup(db: &dyn Store, backup_dir: &Path, version: u32) -> Result<PathBuf, String> {
+    DirBuilder::new()
+        .recursive(true)
+        .create(backup_dir)
+        .map_err(|e| format!("Failed to create {}: {e}", backup_dir.display()))?;
+    let name = format!("pls-v{version}-{}", get_time().unix_timestamp());
+    let mut count = 1;
+    let path = loop {
+        let path = match count {
+            1 => backup_dir.join(format!("{name}.json")),
+            _ => backup_dir.join(format!("{name}-{count}.json")),
+        };
+        // the name is taken before the backup is written, so two upgrades never share it
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => break path,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => count += 1,
+            Err(e) => return Err(format!("Failed to create {}: {e}", path.display())),
+        }
+    };
+    write_copy(db, &path).map_err(|e| format!("Failed to write backup: {e}"))?;
+    Ok(path)
+}
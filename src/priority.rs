@@ -0,0 +1,66 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use yansi::Paint;
+
+// declared from most to least urgent, so sorting by priority puts the most urgent first
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+impl Priority {
+    /// Colored label for the task table.
+    pub fn paint(self) -> String {
+        match self {
+            Self::High => Paint::red("high").bold().to_string(),
+            Self::Medium => Paint::yellow("med").to_string(),
+            Self::Low => Paint::blue("low").to_string(),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "high" | "h" | "!!!" => Ok(Self::High),
+            "medium" | "med" | "m" | "!!" => Ok(Self::Medium),
+            "low" | "l" | "!" => Ok(Self::Low),
+            _ => Err(format!(
+                "Unknown priority \"{s}\", must be high, med or low"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::High => "high",
+            Self::Medium => "med",
+            Self::Low => "low",
+        })
+    }
+}
+
+/// Split an inline priority marker (`!`, `!!` or `!!!`) out of a task title.
+pub fn strip_inline(title: &str) -> (String, Option<Priority>) {
+    let mut priority = None;
+    let words = title
+        .split_whitespace()
+        .filter(|word| {
+            if word.len() <= 3 && word.chars().all(|c| c == '!') {
+                priority = word.parse().ok();
+                false
+            } else {
+                true
+            }
+        })
+        .collect::<Vec<_>>();
+    (words.join(" "), priority)
+}
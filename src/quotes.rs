@@ -1,4 +1,4 @@
-use pickledb::PickleDb;
+use crate::store::Store;
 
 const QUOTE_COUNT: usize = 501;
 
@@ -506,17 +506,17 @@ static QUOTES: [&str; QUOTE_COUNT] = [
     "It's important to know that words don't move mountains. Work, exacting work moves mountains. ~ Danilo Dolci",
 ];
 
-pub fn get_quote(db: &mut PickleDb) -> String {
-    if let Some(current_quote_count) = db.get::<usize>("quote") {
+pub fn get_quote(db: &mut dyn Store) -> String {
+    if let Some(current_quote_count) = db.quote_cursor() {
         if let Some(quote) = QUOTES.get(&current_quote_count + 1) {
-            let _set = db.set("quote", &(current_quote_count + 1));
+            let _set = db.set_quote_cursor(current_quote_count + 1);
             (*quote).to_string()
         } else {
-            let _set = db.set("quote", &0);
+            let _set = db.set_quote_cursor(0);
             QUOTES[0].to_string()
         }
     } else {
-        let _set = db.set("quote", &0);
+        let _set = db.set_quote_cursor(0);
         QUOTES[0].to_string()
     }
 }
@@ -0,0 +1,134 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, Month, OffsetDateTime, Weekday};
+
+/// How often a recurring task comes back. Stored the same way it is typed, ex: "weekly:mon".
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub enum Recurrence {
+    Daily,
+    // monday to friday
+    Weekday,
+    Weekly(Weekday),
+    // day of the month, clamped to the length of shorter months
+    Monthly(u8),
+}
+
+impl Recurrence {
+    /// The first date after `date` that the task recurs on.
+    pub fn next_date(self, date: Date) -> Date {
+        match self {
+            Self::Daily => date + Duration::days(1),
+            Self::Weekday => match date.weekday() {
+                Weekday::Friday => date + Duration::days(3),
+                Weekday::Saturday => date + Duration::days(2),
+                _ => date + Duration::days(1),
+            },
+            Self::Weekly(weekday) => date.next_occurrence(weekday),
+            Self::Monthly(day) => {
+                let this_month = clamp_day(date.year(), date.month(), day);
+                if this_month > date {
+                    this_month
+                } else {
+                    let (year, month) = if date.month() == Month::December {
+                        (date.year() + 1, Month::January)
+                    } else {
+                        (date.year(), date.month().next())
+                    };
+                    clamp_day(year, month, day)
+                }
+            }
+        }
+    }
+
+    /// The due date following `due`, skipping any occurrences already in the past.
+    pub fn next_due(self, due: OffsetDateTime, now: OffsetDateTime) -> OffsetDateTime {
+        let mut next = due.replace_date(self.next_date(due.date()));
+        while next <= now {
+            next = next.replace_date(self.next_date(next.date()));
+        }
+        next
+    }
+
+    /// The first date, from `today` on, that the task recurs on.
+    pub fn first_date(self, today: Date) -> Date {
+        self.next_date(today - Duration::days(1))
+    }
+}
+
+// the given day of the month, or the last day of the month if it is shorter
+fn clamp_day(year: i32, month: Month, day: u8) -> Date {
+    (1..=day)
+        .rev()
+        .find_map(|day| Date::from_calendar_date(year, month, day).ok())
+        .expect("every month has a first day")
+}
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("mon", Weekday::Monday),
+    ("tue", Weekday::Tuesday),
+    ("wed", Weekday::Wednesday),
+    ("thu", Weekday::Thursday),
+    ("fri", Weekday::Friday),
+    ("sat", Weekday::Saturday),
+    ("sun", Weekday::Sunday),
+];
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let error = || {
+            format!("Unknown recurrence \"{s}\", must be daily, weekday, weekly:<day> or monthly:<1-31>")
+        };
+        match s.split_once(':') {
+            None if s == "daily" => Ok(Self::Daily),
+            None if s == "weekday" || s == "weekdays" => Ok(Self::Weekday),
+            Some(("weekly", day)) => WEEKDAYS
+                .iter()
+                .find(|(name, _)| day.starts_with(name))
+                .map(|(_, weekday)| Self::Weekly(*weekday))
+                .ok_or_else(error),
+            Some(("monthly", day)) => day
+                .parse::<u8>()
+                .ok()
+                .filter(|day| (1..=31).contains(day))
+                .map(Self::Monthly)
+                .ok_or_else(error),
+            _ => Err(error()),
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Daily => f.write_str("daily"),
+            Self::Weekday => f.write_str("weekday"),
+            Self::Weekly(weekday) => {
+                let name = WEEKDAYS
+                    .iter()
+                    .find(|(_, w)| w == weekday)
+                    .map_or("mon", |(name, _)| name);
+                write!(f, "weekly:{name}")
+            }
+            Self::Monthly(day) => write!(f, "monthly:{day}"),
+        }
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(value: Recurrence) -> Self {
+        value.to_string()
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use term_table::{
+    row::Row,
+    table_cell::{Alignment, TableCell},
+    TableStyle,
+};
+use yansi::Paint;
+
+use crate::Task;
+
+// words that mark a comment as something left to do
+const MARKERS: [&str; 3] = ["TODO", "FIXME", "HACK"];
+
+// what a marker has to follow, ignoring spaces, to be in a comment rather than in code
+const COMMENT_STARTS: [&str; 8] = ["//", "#", "/*", "*", "--", ";", "<!--", "%"];
+
+/// A TODO, FIXME or HACK comment in a source file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Comment {
+    // relative to the directory the scan was run from, ex: "src/main.rs"
+    pub path: String,
+    // the same file as an absolute path, which doesn't depend on where pls is run
+    pub file: String,
+    // 1-based
+    pub line: usize,
+    pub marker: String,
+    pub text: String,
+}
+
+impl Comment {
+    pub fn location(&self) -> String {
+        format!("{}:{}", self.path, self.line)
+    }
+}
+
+/// The comment a task was imported from by `pls scan --import`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Origin {
+    // absolute, so that a scan run from another directory finds the same file
+    pub path: String,
+    pub line: usize,
+    pub text: String,
+    // set by a later scan that no longer finds the comment
+    #[serde(default)]
+    pub resolved: bool,
+}
+
+impl Origin {
+    // a comment is the same one if it says the same thing in the same file, even if lines
+    // were added above it
+    fn matches(&self, comment: &Comment) -> bool {
+        // origins imported by earlier versions of pls have a relative path
+        let same_file = if Path::new(&self.path).is_absolute() {
+            self.path == comment.file
+        } else {
+            self.path == comment.path
+        };
+        same_file && self.text == comment.text
+    }
+
+    /// The path of the file, relative to the current directory if it is in it.
+    pub fn shown_path(&self) -> String {
+        std::env::current_dir()
+            .ok()
+            .and_then(|dir| fs::canonicalize(dir).ok())
+            .and_then(|dir| {
+                let path = Path::new(&self.path).strip_prefix(dir).ok()?;
+                Some(path.to_string_lossy().replace('\\', "/"))
+            })
+            .unwrap_or_else(|| self.path.clone())
+    }
+}
+
+/// Find the comments in every file under `root`, skipping files ignored by .gitignore files,
+/// hidden files and files that aren't text.
+pub fn scan(root: &Path) -> Result<Vec<Comment>, String> {
+    let absolute_root = absolute(root)?;
+    let mut comments = Vec::new();
+    // .gitignore files count even outside of a git repository
+    for entry in WalkBuilder::new(root).require_git(false).build() {
+        let entry = entry.map_err(|e| format!("Failed to scan {}: {e}", root.display()))?;
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let Ok(text) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        let path = entry.path().to_string_lossy().replace('\\', "/");
+        let path = path.strip_prefix("./").unwrap_or(&path).to_string();
+        let file = entry
+            .path()
+            .strip_prefix(root)
+            .map_or_else(
+                |_| entry.path().to_path_buf(),
+                |rel| absolute_root.join(rel),
+            )
+            .to_string_lossy()
+            .replace('\\', "/");
+        comments.extend(
+            find_comments(&text)
+                .into_iter()
+                .map(|(line, marker, text)| Comment {
+                    path: path.clone(),
+                    file: file.clone(),
+                    line,
+                    marker,
+                    text,
+                }),
+        );
+    }
+    comments.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+    Ok(comments)
+}
+
+/// The line number, marker and text of each comment in a file, ex: `// TODO(ann): fix this`
+/// gives (1, "TODO", "fix this").
+pub fn find_comments(text: &str) -> Vec<(usize, String, String)> {
+    let mut found = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let marker = MARKERS.iter().find_map(|marker| {
+            line.match_indices(marker)
+                .find(|(start, _)| {
+                    let before = line[..*start].trim_end();
+                    let after = line[start + marker.len()..].chars().next();
+                    COMMENT_STARTS.iter().any(|c| before.ends_with(c))
+                        && !after.is_some_and(|c| c.is_alphanumeric() || c == '_')
+                })
+                .map(|(start, _)| (*marker, &line[start + marker.len()..]))
+        });
+        let Some((marker, rest)) = marker else {
+            continue;
+        };
+        // an author in brackets, ex: TODO(ann), and the punctuation after the marker
+        let rest = match rest.strip_prefix('(').and_then(|r| r.split_once(')')) {
+            Some((_, rest)) => rest,
+            None => rest,
+        };
+        let text = rest
+            .trim_start_matches([':', '-', ' ', '\t'])
+            .trim_end()
+            .trim_end_matches("*/")
+            .trim_end_matches("-->")
+            .trim();
+        let text = if text.is_empty() { marker } else { text };
+        found.push((number + 1, marker.to_string(), text.to_string()));
+    }
+    found
+}
+
+/// Follow the comments that tasks were imported from: update their line numbers, and flag
+/// the tasks whose comment is gone as resolved, or no longer resolved if it came back. Only
+/// tasks from files under `root` are checked. Returns the positions of the tasks that have
+/// just been resolved.
+pub fn update_origins(tasks: &mut [Task], comments: &[Comment], root: &Path) -> Vec<usize> {
+    let mut resolved = Vec::new();
+    // a root that doesn't exist has no comments to resolve tasks with
+    let Ok(root) = absolute(root) else {
+        return resolved;
+    };
+    for (index, task) in tasks.iter_mut().enumerate() {
+        let Some(origin) = task.origin.as_mut() else {
+            continue;
+        };
+        let before = origin.clone();
+        if let Some(comment) = comments.iter().find(|c| origin.matches(c)) {
+            origin.path.clone_from(&comment.file);
+            origin.line = comment.line;
+            origin.resolved = false;
+        } else if Path::new(&origin.path).starts_with(&root) {
+            if !origin.resolved {
+                resolved.push(index);
+            }
+            origin.resolved = true;
+        }
+        if *origin != before {
+            task.touch();
+        }
+    }
+    resolved
+}
+
+/// Add a task for every comment that no task was imported from yet, tagged with its marker in
+/// lowercase, ex: +fixme. Returns how many were added.
+pub fn import(
+    tasks: &mut Vec<Task>,
+    comments: &[Comment],
+    mut next_id: impl FnMut() -> u64,
+) -> usize {
+    let mut added = 0;
+    for comment in comments {
+        let imported = tasks
+            .iter()
+            .any(|t| t.origin.as_ref().is_some_and(|o| o.matches(comment)));
+        if imported {
+            continue;
+        }
+        let mut task = Task::new(&comment.text);
+        task.id = next_id();
+        task.tags = vec![comment.marker.to_lowercase()];
+        task.origin = Some(Origin {
+            path: comment.file.clone(),
+            line: comment.line,
+            text: comment.text.clone(),
+            resolved: false,
+        });
+        tasks.push(task);
+        added += 1;
+    }
+    added
+}
+
+/// Show the comments as a table, along with the task each was imported into.
+pub fn print_comments(comments: &[Comment], tasks: &[Task]) {
+    let mut table = term_table::Table::new();
+    table.style = TableStyle::extended();
+    if comments.is_empty() {
+        table.add_row(Row::new(vec![TableCell::new_with_alignment(
+            Paint::green("No TODO, FIXME or HACK comments found!"),
+            1,
+            Alignment::Center,
+        )]));
+        println!("{}", table.render());
+        return;
+    }
+    table.add_row(Row::new(Vec::from(["Task", "Comment", "Location"].map(
+        |column| {
+            TableCell::new_with_alignment(
+                Paint::yellow(column).bold().italic(),
+                1,
+                Alignment::Center,
+            )
+        },
+    ))));
+    for comment in comments {
+        let task = tasks
+            .iter()
+            .find(|t| t.origin.as_ref().is_some_and(|o| o.matches(comment)));
+        table.add_row(Row::new(vec![
+            TableCell::new_with_alignment(
+                task.map_or_else(|| "-".to_string(), |t| Paint::green(&t.id).to_string()),
+                1,
+                Alignment::Center,
+            ),
+            TableCell::new_with_alignment(
+                format!(
+                    "{} {}",
+                    Paint::red(&comment.marker).bold(),
+                    Paint::green(&comment.text)
+                ),
+                1,
+                Alignment::Left,
+            ),
+            TableCell::new_with_alignment(Paint::cyan(&comment.location()), 1, Alignment::Left),
+        ]));
+    }
+    println!("{}", table.render());
+}
+
+// the directory as an absolute path without symlinks, ex: /home/me/code/pls for "."
+fn absolute(dir: &Path) -> Result<PathBuf, String> {
+    fs::canonicalize(dir).map_err(|e| format!("Failed to scan {}: {e}", dir.display()))
+}
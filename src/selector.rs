@@ -0,0 +1,68 @@
+use crate::{filter::Filter, find_task, Task};
+
+/// Resolve a selector to positions in the task list. A selector is made of comma or space
+/// separated parts, each of which is one of:
+/// - a task id, such as `3`
+/// - an inclusive range of ids, such as `4-6`, matching at least one task
+/// - `last`, the most recently added task, or `all`
+/// - `+tag` and `@project` tokens, which together select every task matching them
+///
+/// With no selector, the first task is selected.
+pub fn select(tasks: &[Task], selector: &[String]) -> Result<Vec<usize>, String> {
+    let parts = selector
+        .iter()
+        .flat_map(|s| s.split([',', ' ']))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    if parts.is_empty() {
+        return find_task(tasks, None)
+            .map(|index| vec![index])
+            .ok_or_else(|| "there are no tasks".to_string());
+    }
+
+    let mut indices = Vec::new();
+    let mut filters = Vec::new();
+    for part in &parts {
+        match *part {
+            "last" => indices.extend(tasks.len().checked_sub(1)),
+            "all" => indices.extend(0..tasks.len()),
+            _ if part.starts_with(['+', '@']) => filters.push((*part).to_string()),
+            _ => {
+                // a single id is the range of just that id, so that both mean the same thing
+                let (start, end) = part.split_once('-').unwrap_or((part, part));
+                let (Ok(start), Ok(end)) = (start.parse::<u64>(), end.parse::<u64>()) else {
+                    return Err(format!(
+                        "\"{part}\" is not a task id, range, \"last\", \"all\", +tag or @project"
+                    ));
+                };
+                let matched = tasks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, t)| (start..=end).contains(&t.id))
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>();
+                if matched.is_empty() {
+                    return Err(format!("no task exists with id {part}"));
+                }
+                indices.extend(matched);
+            }
+        }
+    }
+    if !filters.is_empty() {
+        let filter = Filter::parse(&filters)?;
+        indices.extend(
+            tasks
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| filter.matches(t))
+                .map(|(index, _)| index),
+        );
+    }
+
+    indices.sort_unstable();
+    indices.dedup();
+    if indices.is_empty() {
+        return Err(format!("\"{}\" doesn't match any tasks", parts.join(",")));
+    }
+    Ok(indices)
+}
@@ -0,0 +1,954 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    checklist::{self, Checklist, Style},
+    crypto::{self, Key},
+    Task,
+};
+
+/// Where pls keeps its tasks, settings, weather cache and quote cursor. Values are JSON, so a
+/// backend only has to store JSON values by key.
+pub trait Store {
+    fn get_value(&self, key: &str) -> Option<Value>;
+    fn set_value(&mut self, key: &str, value: Value) -> Result<(), String>;
+    fn remove_value(&mut self, key: &str) -> Result<(), String>;
+    fn keys(&self) -> Vec<String>;
+
+    /// The key the store is encrypted with, if it is.
+    fn key(&self) -> Option<&Key> {
+        None
+    }
+
+    /// Start a group of writes that are saved together by `commit`. Groups can be nested.
+    fn begin(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn tasks(&self) -> Vec<Task> {
+        self.get_value("tasks")
+            .and_then(|tasks| serde_json::from_value(tasks).ok())
+            .unwrap_or_default()
+    }
+
+    fn set_tasks(&mut self, tasks: &[Task]) -> Result<(), String> {
+        let tasks = serde_json::to_value(tasks).map_err(|e| e.to_string())?;
+        self.set_value("tasks", tasks)
+    }
+}
+
+impl dyn Store + '_ {
+    pub fn get<V: DeserializeOwned>(&self, key: &str) -> Option<V> {
+        serde_json::from_value(self.get_value(key)?).ok()
+    }
+
+    pub fn set<V: Serialize + ?Sized>(&mut self, key: &str, value: &V) -> Result<(), String> {
+        let value = serde_json::to_value(value).map_err(|e| e.to_string())?;
+        self.set_value(key, value)
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.get_value(key).is_some()
+    }
+
+    pub fn rem(&mut self, key: &str) -> Result<(), String> {
+        self.remove_value(key)
+    }
+
+    /// A setting changed with `pls config`, if it has been set.
+    pub fn setting(&self, key: &str) -> Option<String> {
+        self.get(key)
+    }
+
+    pub fn cached_weather(&self) -> Option<String> {
+        self.get("weather-cached")
+    }
+
+    /// When the cached weather was fetched, as a unix timestamp.
+    pub fn weather_timestamp(&self) -> Option<i64> {
+        self.get("weather-timestamp")
+    }
+
+    pub fn cache_weather(&mut self, weather: &str, timestamp: i64) -> Result<(), String> {
+        self.begin()?;
+        self.set("weather-cached", weather)?;
+        self.set("weather-timestamp", &timestamp)?;
+        self.commit()
+    }
+
+    /// Position in the quote list of the last quote shown.
+    pub fn quote_cursor(&self) -> Option<usize> {
+        self.get("quote")
+    }
+
+    pub fn set_quote_cursor(&mut self, cursor: usize) -> Result<(), String> {
+        self.set("quote", &cursor)
+    }
+}
+
+impl Store for PickleDb {
+    fn get_value(&self, key: &str) -> Option<Value> {
+        self.get::<Value>(key)
+    }
+
+    fn set_value(&mut self, key: &str, value: Value) -> Result<(), String> {
+        self.set(key, &value).map_err(|e| e.to_string())
+    }
+
+    fn remove_value(&mut self, key: &str) -> Result<(), String> {
+        self.rem(key).map(drop).map_err(|e| e.to_string())
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.get_all()
+    }
+}
+
+/// Open the store in `dir`. The sqlite store is used when pls is built with the `sqlite`
+/// feature, unless `PLS_STORAGE` is set to `json` (or `sqlite`, to pick it explicitly).
+pub fn open(dir: &Path) -> Result<Box<dyn Store>, String> {
+    let json_path = dir.join("pls.json");
+    let backend = std::env::var("PLS_STORAGE").unwrap_or_default();
+    match backend.as_str() {
+        "json" => open_json(&json_path),
+        #[cfg(feature = "sqlite")]
+        "" | "sqlite" => {
+            let sqlite_path = dir.join("pls.sqlite3");
+            let is_new = !sqlite_path.exists();
+            let mut store = sqlite::SqliteStore::open(&sqlite_path)?;
+            // carry everything over from the json store the first time sqlite is used
+            if is_new && json_path.exists() {
+                copy_all(open_json(&json_path)?.as_ref(), &mut store)?;
+            }
+            Ok(Box::new(store))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        "" => open_json(&json_path),
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" => Err("pls was built without the sqlite feature".to_string()),
+        _ => Err(format!(
+            "unknown storage \"{backend}\" in PLS_STORAGE, must be json or sqlite"
+        )),
+    }
+}
+
+/// Open a copy of pls.json, such as one from another machine, without ever writing to it. The
+/// key of an encrypted copy isn't kept after it's read.
+pub fn open_read_only(path: &Path) -> Result<Box<dyn Store>, String> {
+    let contents = fs::read(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    if crypto::is_encrypted(&contents) {
+        let (_, contents) = crypto::unlock(path, &contents, false)?;
+        return from_contents(path, &contents).map(|db| Box::new(db) as Box<dyn Store>);
+    }
+    PickleDb::load_read_only(path, SerializationMethod::Json)
+        .map(|db| Box::new(db) as Box<dyn Store>)
+        .map_err(|e| format!("Failed to open {}: {e}", path.display()))
+}
+
+/// Open pls.json in `dir` itself, to encrypt or decrypt it. The sqlite database can't be.
+pub fn open_pls_json(dir: &Path) -> Result<JsonStore, String> {
+    #[cfg(feature = "sqlite")]
+    if std::env::var("PLS_STORAGE").unwrap_or_default() != "json" {
+        return Err(
+            "only pls.json can be encrypted, set PLS_STORAGE=json to use it instead of \
+             pls.sqlite3"
+                .to_string(),
+        );
+    }
+    JsonStore::open(&dir.join("pls.json"))
+}
+
+fn open_json(path: &Path) -> Result<Box<dyn Store>, String> {
+    JsonStore::open(path).map(|store| Box::new(store) as Box<dyn Store>)
+}
+
+/// The pickledb file, made safe to share between several pls processes, such as the background
+/// weather refresh and an interactive `pls add`. Every write locks the file, reloads it to pick
+/// up what other processes saved, applies the change and writes the whole file atomically, so
+/// that processes changing different keys never undo each other's changes.
+pub struct JsonStore {
+    path: PathBuf,
+    db: PickleDb,
+    // the file itself is replaced on every write, so the lock is taken on another file
+    lock: File,
+    // how many transactions are open
+    depth: usize,
+    // written the way pickledb does, or as a plain json object
+    plain: bool,
+    // set when the file is encrypted
+    key: Option<Key>,
+}
+
+impl JsonStore {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        Self::open_with(path, &path.with_extension("json.lock"), false, None)
+    }
+
+    /// Open a file the config directory keeps besides pls.json, such as a journal, encrypted
+    /// with the key of pls.json if it is, so that it doesn't keep tasks in plain text.
+    pub fn open_private(path: &Path, key: Option<&Key>) -> Result<Self, String> {
+        let mut store =
+            Self::open_with(path, &path.with_extension("json.lock"), false, key.cloned())?;
+        if key.is_some() && store.key.as_ref() != key {
+            store.set_key(key.cloned())?;
+        }
+        Ok(store)
+    }
+
+    /// Open a json file holding an object of keys and values, pretty printed so that it is
+    /// easy to read and diff.
+    pub fn open_plain(path: &Path, lock_path: &Path) -> Result<Self, String> {
+        Self::open_with(path, lock_path, true, None)
+    }
+
+    // the key, if given, is tried first when the file is encrypted
+    fn open_with(
+        path: &Path,
+        lock_path: &Path,
+        plain: bool,
+        key: Option<Key>,
+    ) -> Result<Self, String> {
+        let lock = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path)
+            .map_err(|e| format!("Failed to open {}: {e}", lock_path.display()))?;
+        let mut store = Self {
+            path: path.to_path_buf(),
+            db: PickleDb::new_json(path, PickleDbDumpPolicy::NeverDump),
+            lock,
+            depth: 0,
+            plain,
+            key,
+        };
+        // loaded under the lock too, so that a file being written is never read
+        store.lock()?;
+        let loaded = store.load();
+        drop(store.lock.unlock());
+        loaded.map(|()| store)
+    }
+
+    /// Write the file again encrypted with a key, or decrypted with none.
+    pub fn set_key(&mut self, key: Option<Key>) -> Result<(), String> {
+        self.begin()?;
+        self.key = key;
+        self.commit()
+    }
+
+    fn lock(&self) -> Result<(), String> {
+        self.lock
+            .lock()
+            .map_err(|e| format!("Failed to lock {}: {e}", self.path.display()))
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        if self.plain {
+            let values = match fs::read_to_string(&self.path) {
+                Ok(text) => serde_json::from_str::<BTreeMap<String, Value>>(&text)
+                    .map_err(|e| format!("Failed to read {}: {e}", self.path.display()))?,
+                Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => return Err(format!("Failed to open {}: {e}", self.path.display())),
+            };
+            self.db = PickleDb::new_json(&self.path, PickleDbDumpPolicy::NeverDump);
+            for (key, value) in values {
+                self.db.set_value(&key, value)?;
+            }
+            return Ok(());
+        }
+        // the file is encrypted or not whatever it was before, ex: after `pls decrypt`
+        match fs::read(&self.path) {
+            Ok(contents) if crypto::is_encrypted(&contents) => {
+                let decrypted = self
+                    .key
+                    .as_ref()
+                    .and_then(|key| crypto::decrypt(key, &contents).ok());
+                let decrypted = if let Some(decrypted) = decrypted {
+                    decrypted
+                } else {
+                    let (key, decrypted) = crypto::unlock(&self.path, &contents, true)?;
+                    self.key = Some(key);
+                    decrypted
+                };
+                self.db = from_contents(&self.path, &decrypted)?;
+                return Ok(());
+            }
+            _ => self.key = None,
+        }
+        self.db = PickleDb::load_or_new(
+            &self.path,
+            PickleDbDumpPolicy::NeverDump,
+            SerializationMethod::Json,
+        )
+        .map_err(|e| format!("Failed to open {}: {e}", self.path.display()))?;
+        Ok(())
+    }
+
+    // as pickledb writes it, or as a plain json object
+    fn contents(&self) -> Result<String, String> {
+        let values = self
+            .db
+            .get_all()
+            .into_iter()
+            .filter_map(|key| Some((key.clone(), self.db.get_value(&key)?)))
+            .collect::<BTreeMap<_, _>>();
+        let contents = if self.plain {
+            serde_json::to_string_pretty(&values).map(|json| json + "\n")
+        } else {
+            pickledb_layout(values)
+        };
+        contents.map_err(|e| format!("Failed to serialize {}: {e}", self.path.display()))
+    }
+
+    // make a single change in its own transaction
+    fn change(
+        &mut self,
+        change: impl FnOnce(&mut PickleDb) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.begin()?;
+        let changed = change(&mut self.db);
+        let committed = self.commit();
+        changed.and(committed)
+    }
+}
+
+impl Store for JsonStore {
+    fn get_value(&self, key: &str) -> Option<Value> {
+        self.db.get_value(key)
+    }
+
+    fn set_value(&mut self, key: &str, value: Value) -> Result<(), String> {
+        self.change(|db| db.set_value(key, value))
+    }
+
+    fn remove_value(&mut self, key: &str) -> Result<(), String> {
+        self.change(|db| db.remove_value(key))
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.db.get_all()
+    }
+
+    fn key(&self) -> Option<&Key> {
+        self.key.as_ref()
+    }
+
+    // the lock is held from loading the file to writing it back, so no other process can
+    // write in between
+    fn begin(&mut self) -> Result<(), String> {
+        if self.depth == 0 {
+            self.lock()?;
+            if let Err(e) = self.load() {
+                drop(self.lock.unlock());
+                return Err(e);
+            }
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth > 0 {
+            return Ok(());
+        }
+        let written = self
+            .contents()
+            .and_then(|contents| match &self.key {
+                Some(key) => crypto::encrypt(key, contents.as_bytes()),
+                None => Ok(contents.into_bytes()),
+            })
+            .and_then(|contents| write_atomic(&self.path, &contents));
+        drop(self.lock.unlock());
+        written
+    }
+}
+
+/// Write a file by writing a temporary file next to it and renaming it into place, so that
+/// the file is never left half written, even if pls is killed halfway through. The file keeps
+/// its permissions, and a new one can only be read by its owner.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // only readable by its owner from the start, as the tasks could be read before the rename
+    // otherwise, and never a file that was already there
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut attempts = 0;
+    let (temp, mut file) = loop {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let temp =
+            path.with_file_name(format!(".{file_name}.{}-{nanos:x}.tmp", std::process::id()));
+        match options.open(&temp) {
+            Ok(file) => break (temp, file),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists && attempts < 100 => attempts += 1,
+            Err(e) => return Err(format!("Failed to write {}: {e}", path.display())),
+        }
+    };
+    let mut write = || -> std::io::Result<()> {
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    };
+    write().map_err(|e| {
+        drop(fs::remove_file(&temp));
+        format!("Failed to write {}: {e}", path.display())
+    })
+}
+
+/// Write everything in a store to a new file, that can be copied over pls.json to restore it.
+/// The file is encrypted if the store is.
+pub fn write_copy(from: &dyn Store, path: &Path) -> Result<(), String> {
+    let values = from
+        .keys()
+        .into_iter()
+        .filter_map(|key| Some((key.clone(), from.get_value(&key)?)))
+        .collect();
+    let contents = pickledb_layout(values)
+        .map_err(|e| format!("Failed to serialize {}: {e}", path.display()))?;
+    let contents = match from.key() {
+        Some(key) => crypto::encrypt(key, contents.as_bytes())?,
+        None => contents.into_bytes(),
+    };
+    write_atomic(path, &contents)
+}
+
+/// Encrypt the copies of the tasks the config directory keeps besides pls.json with its key, or
+/// decrypt them with none: the journals of projects and linked files, and the backups. `old` is
+/// the key they were encrypted with, if they were.
+pub fn encrypt_copies(dir: &Path, old: Option<&Key>, key: Option<&Key>) -> Result<(), String> {
+    let files = |name: &str| {
+        fs::read_dir(dir.join(name))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect::<Vec<_>>()
+    };
+    for path in files("projects").into_iter().chain(files("linked")) {
+        let lock_path = path.with_extension("json.lock");
+        let mut store = JsonStore::open_with(&path, &lock_path, false, old.cloned())?;
+        if store.key.as_ref() != key {
+            store.set_key(key.cloned())?;
+        }
+    }
+    for path in files("backups") {
+        let contents =
+            fs::read(&path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let contents = if crypto::is_encrypted(&contents) {
+            // one encrypted with an earlier passphrase is left as it is
+            match old.and_then(|old| crypto::decrypt(old, &contents).ok()) {
+                Some(contents) => contents,
+                None => continue,
+            }
+        } else {
+            contents
+        };
+        let contents = match key {
+            Some(key) => crypto::encrypt(key, &contents)?,
+            None => contents,
+        };
+        write_atomic(&path, &contents)?;
+    }
+    Ok(())
+}
+
+// the same layout pickledb writes: every value as a json string, followed by the lists, which
+// pls doesn't use
+fn pickledb_layout(values: BTreeMap<String, Value>) -> serde_json::Result<String> {
+    let values = values
+        .into_iter()
+        .map(|(key, value)| (key, value.to_string()))
+        .collect::<BTreeMap<_, _>>();
+    serde_json::to_string(&(values, BTreeMap::<String, Vec<String>>::new()))
+}
+
+// a database from the contents of a file in the layout pickledb writes, which is only kept in
+// memory
+fn from_contents(path: &Path, contents: &[u8]) -> Result<PickleDb, String> {
+    let error = |e: serde_json::Error| format!("Failed to read {}: {e}", path.display());
+    let (values, _) =
+        serde_json::from_slice::<(BTreeMap<String, String>, Value)>(contents).map_err(error)?;
+    let mut db = PickleDb::new_json(path, PickleDbDumpPolicy::NeverDump);
+    for (key, value) in values {
+        db.set_value(&key, serde_json::from_str(&value).map_err(error)?)?;
+    }
+    Ok(db)
+}
+
+/// Copy every key from one store into another, in a single transaction.
+#[cfg(feature = "sqlite")]
+pub fn copy_all(from: &dyn Store, to: &mut dyn Store) -> Result<(), String> {
+    to.begin()?;
+    for key in from.keys() {
+        if let Some(value) = from.get_value(&key) {
+            to.set_value(&key, value)?;
+        }
+    }
+    to.commit()
+}
+
+/// Find the tasks file of the project the directory is in, by looking for a `.pls.json` file
+/// or a `.pls` directory (which keeps them in `tasks.json`) in it and each of its parents.
+pub fn find_project(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find_map(|dir| {
+        let file = dir.join(".pls.json");
+        let folder = dir.join(".pls");
+        if file.is_file() {
+            Some(file)
+        } else if folder.is_dir() {
+            Some(folder.join("tasks.json"))
+        } else {
+            None
+        }
+    })
+}
+
+// the keys a project shares through its tasks file
+const PROJECT_KEYS: [&str; 3] = ["tasks", "task-id", "schema_version"];
+
+/// The tasks of a project, in a file that can be committed along with it. Only the tasks and
+/// their id counter are kept in that file, everything else, such as the journal, is kept in
+/// the config directory so that it isn't shared.
+pub struct ProjectStore {
+    shared: JsonStore,
+    private: JsonStore,
+}
+
+impl ProjectStore {
+    pub fn open(path: &Path, config_dir: &Path, key: Option<&Key>) -> Result<Self, String> {
+        let dir = config_dir.join("projects");
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+        // named after the path of the project, ex: home%me%code%pls%.pls.json
+        let name = file_name_for(path);
+        let shared = JsonStore::open_plain(path, &dir.join(format!("{name}.lock")))?;
+        let private = JsonStore::open_private(&dir.join(format!("{name}.private.json")), key)?;
+        Ok(Self { shared, private })
+    }
+
+    fn store(&mut self, key: &str) -> &mut JsonStore {
+        if PROJECT_KEYS.contains(&key) {
+            &mut self.shared
+        } else {
+            &mut self.private
+        }
+    }
+}
+
+impl Store for ProjectStore {
+    fn get_value(&self, key: &str) -> Option<Value> {
+        if PROJECT_KEYS.contains(&key) {
+            self.shared.get_value(key)
+        } else {
+            self.private.get_value(key)
+        }
+    }
+
+    fn set_value(&mut self, key: &str, value: Value) -> Result<(), String> {
+        self.store(key).set_value(key, value)
+    }
+
+    fn remove_value(&mut self, key: &str) -> Result<(), String> {
+        self.store(key).remove_value(key)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let mut keys = self.shared.keys();
+        keys.extend(self.private.keys());
+        keys
+    }
+
+    fn begin(&mut self) -> Result<(), String> {
+        self.shared.begin()?;
+        self.private
+            .begin()
+            .inspect_err(|_| drop(self.shared.commit()))
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        let shared = self.shared.commit();
+        let private = self.private.commit();
+        shared.and(private)
+    }
+}
+
+// the keys of a linked file kept in the config directory, apart from the global ones
+const LINKED_KEYS: [&str; 6] = [
+    "journal",
+    "journal-redo",
+    "schema_version",
+    "tombstones",
+    "merge-base",
+    "merge-ids",
+];
+
+/// A Markdown or Org checklist linked with `pls link`, which takes the place of the global
+/// tasks. The file is read again on every transaction, so it can be edited by hand between
+/// commands. The journal of its changes is kept in the config directory, and everything else,
+/// such as settings, stays in the global store.
+pub struct LinkedStore<'a> {
+    path: PathBuf,
+    checklist: Checklist,
+    // also holds the lock on the file
+    private: JsonStore,
+    global: &'a mut dyn Store,
+    // how many transactions are open
+    depth: usize,
+    // whether the checklist needs to be written back
+    changed: bool,
+}
+
+impl<'a> LinkedStore<'a> {
+    pub fn open(path: &Path, config_dir: &Path, global: &'a mut dyn Store) -> Result<Self, String> {
+        let dir = config_dir.join("linked");
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+        let private = JsonStore::open_private(
+            &dir.join(format!("{}.json", file_name_for(path))),
+            global.key(),
+        )?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            checklist: read_checklist(path)?,
+            private,
+            global,
+            depth: 0,
+            changed: false,
+        })
+    }
+}
+
+impl Store for LinkedStore<'_> {
+    fn get_value(&self, key: &str) -> Option<Value> {
+        match key {
+            "tasks" => serde_json::to_value(self.tasks()).ok(),
+            // ids are positions in the file, so the next task is the one after the last
+            "task-id" => Some(Value::from(self.tasks().len() + 1)),
+            _ if LINKED_KEYS.contains(&key) => self.private.get_value(key),
+            _ => self.global.get_value(key),
+        }
+    }
+
+    fn set_value(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match key {
+            "tasks" => {
+                let tasks = serde_json::from_value::<Vec<Task>>(value)
+                    .map_err(|e| format!("Invalid tasks: {e}"))?;
+                self.set_tasks(&tasks)
+            }
+            "task-id" => Ok(()),
+            _ if LINKED_KEYS.contains(&key) => self.private.set_value(key, value),
+            _ => self.global.set_value(key, value),
+        }
+    }
+
+    fn remove_value(&mut self, key: &str) -> Result<(), String> {
+        match key {
+            "tasks" => self.set_tasks(&[]),
+            "task-id" => Ok(()),
+            _ if LINKED_KEYS.contains(&key) => self.private.remove_value(key),
+            _ => self.global.remove_value(key),
+        }
+    }
+
+    fn key(&self) -> Option<&Key> {
+        self.global.key()
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let mut keys = self
+            .global
+            .keys()
+            .into_iter()
+            .filter(|key| {
+                key != "tasks" && key != "task-id" && !LINKED_KEYS.contains(&key.as_str())
+            })
+            .collect::<Vec<_>>();
+        keys.extend(self.private.keys());
+        keys.push("tasks".to_string());
+        keys
+    }
+
+    fn begin(&mut self) -> Result<(), String> {
+        self.private.begin()?;
+        if self.depth == 0 {
+            match read_checklist(&self.path) {
+                Ok(checklist) => self.checklist = checklist,
+                Err(e) => {
+                    drop(self.private.commit());
+                    return Err(e);
+                }
+            }
+        }
+        self.depth += 1;
+        self.global.begin()
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        self.depth = self.depth.saturating_sub(1);
+        let written = if self.depth == 0 && self.changed {
+            self.changed = false;
+            write_atomic(&self.path, self.checklist.text().as_bytes())
+        } else {
+            Ok(())
+        };
+        let private = self.private.commit();
+        let global = self.global.commit();
+        written.and(private).and(global)
+    }
+
+    fn tasks(&self) -> Vec<Task> {
+        self.checklist.tasks()
+    }
+
+    // refused rather than dropping what the file can't hold
+    fn set_tasks(&mut self, tasks: &[Task]) -> Result<(), String> {
+        for task in tasks {
+            let unsupported = checklist::unsupported(task);
+            if !unsupported.is_empty() {
+                return Err(format!(
+                    "{} can't hold the {} of task {} ({}), unlink it to use them",
+                    self.path.display(),
+                    unsupported.join(" or "),
+                    task.id,
+                    task.title
+                ));
+            }
+        }
+        self.begin()?;
+        self.checklist.update(tasks);
+        self.changed = true;
+        self.commit()
+    }
+}
+
+// a missing file is an empty checklist, created by the first change
+fn read_checklist(path: &Path) -> Result<Checklist, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to open {}: {e}", path.display())),
+    };
+    Ok(Checklist::parse(&text, Style::from_path(path)))
+}
+
+// the name of a file in the config directory that belongs to another file, ex:
+// home%me%notes%todo.md
+fn file_name_for(path: &Path) -> String {
+    let name = path.to_string_lossy().replace(['/', '\\', ':'], "%");
+    name.trim_start_matches('%').to_string()
+}
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    use std::{path::Path, time::Duration};
+
+    use rusqlite::{params, Connection, OptionalExtension};
+    use serde_json::Value;
+
+    use super::Store;
+    use crate::Task;
+
+    /// Keeps tasks in their own table, one row per task, and everything else as JSON in a
+    /// key-value table. Every write happens in a transaction.
+    pub struct SqliteStore {
+        conn: Connection,
+        // how many transactions are open, as begin can be called inside another
+        depth: usize,
+    }
+
+    impl SqliteStore {
+        pub fn open(path: &Path) -> Result<Self, String> {
+            let conn = Connection::open(path)
+                .map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+            // wait for other pls processes to finish writing instead of failing
+            conn.busy_timeout(Duration::from_secs(10))
+                .map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS kv (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+                 CREATE TABLE IF NOT EXISTS tasks (position INTEGER PRIMARY KEY, task TEXT NOT NULL);",
+            )
+            .map_err(|e| format!("Failed to create tables: {e}"))?;
+            Ok(Self { conn, depth: 0 })
+        }
+
+        fn execute(&self, sql: &str) -> Result<(), String> {
+            self.conn.execute_batch(sql).map_err(|e| e.to_string())
+        }
+
+        // undo what the innermost transaction wrote, and close it
+        fn rollback(&mut self) -> Result<(), String> {
+            self.depth = self.depth.saturating_sub(1);
+            if self.depth == 0 {
+                self.execute("ROLLBACK")
+            } else {
+                self.execute("ROLLBACK TO pls; RELEASE pls")
+            }
+        }
+
+        // the tasks as they are stored, in order
+        fn rows(&self) -> Vec<String> {
+            self.conn
+                .prepare("SELECT task FROM tasks ORDER BY position")
+                .and_then(|mut stmt| {
+                    stmt.query_map([], |row| row.get::<_, String>(0))?
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .unwrap_or_default()
+        }
+    }
+
+    impl Store for SqliteStore {
+        fn get_value(&self, key: &str) -> Option<Value> {
+            if key == "tasks" {
+                return self
+                    .keys()
+                    .iter()
+                    .any(|k| k == "tasks")
+                    .then(|| serde_json::to_value(self.tasks()).unwrap_or_default());
+            }
+            self.conn
+                .query_row("SELECT value FROM kv WHERE key = ?1", [key], |row| {
+                    row.get::<_, String>(0)
+                })
+                .optional()
+                .ok()
+                .flatten()
+                .and_then(|value| serde_json::from_str(&value).ok())
+        }
+
+        fn set_value(&mut self, key: &str, value: Value) -> Result<(), String> {
+            if key == "tasks" {
+                let tasks = serde_json::from_value::<Vec<Task>>(value)
+                    .map_err(|e| format!("Invalid tasks: {e}"))?;
+                return self.set_tasks(&tasks);
+            }
+            self.conn
+                .execute(
+                    "INSERT INTO kv (key, value) VALUES (?1, ?2)
+                     ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                    params![key, value.to_string()],
+                )
+                .map(drop)
+                .map_err(|e| e.to_string())
+        }
+
+        fn remove_value(&mut self, key: &str) -> Result<(), String> {
+            let removed = if key == "tasks" {
+                self.conn.execute("DELETE FROM tasks", [])
+            } else {
+                self.conn.execute("DELETE FROM kv WHERE key = ?1", [key])
+            };
+            removed.map(drop).map_err(|e| e.to_string())
+        }
+
+        fn keys(&self) -> Vec<String> {
+            let mut keys = self
+                .conn
+                .prepare("SELECT key FROM kv")
+                .and_then(|mut stmt| {
+                    stmt.query_map([], |row| row.get::<_, String>(0))?
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .unwrap_or_default();
+            let has_tasks = self
+                .conn
+                .query_row("SELECT EXISTS (SELECT 1 FROM tasks)", [], |row| row.get(0))
+                .unwrap_or(false);
+            if has_tasks {
+                keys.push("tasks".to_string());
+            }
+            keys
+        }
+
+        // the outermost transaction takes the write lock right away, so that another pls
+        // process waits for it instead of failing to write what it read, and the ones inside
+        // it are savepoints
+        fn begin(&mut self) -> Result<(), String> {
+            let sql = if self.depth == 0 {
+                "BEGIN IMMEDIATE"
+            } else {
+                "SAVEPOINT pls"
+            };
+            self.execute(sql)?;
+            self.depth += 1;
+            Ok(())
+        }
+
+        fn commit(&mut self) -> Result<(), String> {
+            self.depth = self.depth.saturating_sub(1);
+            if self.depth == 0 {
+                self.execute("COMMIT")
+            } else {
+                self.execute("RELEASE pls")
+            }
+        }
+
+        // rows a newer or damaged pls wrote that can't be read as tasks are left out of the
+        // list, and kept as they are by set_tasks rather than lost
+        fn tasks(&self) -> Vec<Task> {
+            self.rows()
+                .iter()
+                .filter_map(|task| serde_json::from_str(task).ok())
+                .collect()
+        }
+
+        fn set_tasks(&mut self, tasks: &[Task]) -> Result<(), String> {
+            self.begin()?;
+            let result = (|| {
+                let unreadable = self
+                    .rows()
+                    .into_iter()
+                    .filter(|row| serde_json::from_str::<Task>(row).is_err())
+                    .collect::<Vec<_>>();
+                self.conn
+                    .execute("DELETE FROM tasks", [])
+                    .map_err(|e| e.to_string())?;
+                let mut insert = self
+                    .conn
+                    .prepare("INSERT INTO tasks (position, task) VALUES (?1, ?2)")
+                    .map_err(|e| e.to_string())?;
+                let tasks = tasks
+                    .iter()
+                    .map(serde_json::to_string)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| e.to_string())?;
+                for (position, task) in tasks.iter().chain(&unreadable).enumerate() {
+                    insert
+                        .execute(params![position, task])
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            })();
+            match result {
+                Ok(()) => self.commit(),
+                Err(e) => {
+                    drop(self.rollback());
+                    Err(e)
+                }
+            }
+        }
+    }
+}
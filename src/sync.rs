@@ -0,0 +1,193 @@
+use std::{fs, path::Path, process::Command};
+
+use crate::{
+    journal,
+    merge::{self, Side, Strategy},
+    store::Store,
+    Task,
+};
+
+// the file the tasks are synced in, in the config directory
+const FILE: &str = "tasks.json";
+
+// the branch synced with the remote
+const BRANCH: &str = "main";
+
+/// What a sync changed in the local tasks.
+pub struct Synced {
+    pub remote: String,
+    pub before: Vec<Task>,
+    pub after: Vec<Task>,
+}
+
+/// Make the config directory a git repository synced with `remote`, a git URL or the path
+/// of another repository, ex: a bare repository on a shared drive. Only the synced tasks file
+/// is committed, the rest of the directory is ignored. Running it again changes the remote.
+pub fn init(dir: &Path, remote: &str) -> Result<(), String> {
+    // a path is kept in full, since git resolves it from the directory it runs in
+    let remote = fs::canonicalize(remote).map_or_else(
+        |_| remote.to_string(),
+        |path| path.to_string_lossy().to_string(),
+    );
+    if dir.join(".git").exists() {
+        return git(dir, &["remote", "set-url", "origin", &remote]).map(drop);
+    }
+    git(dir, &["init", "--quiet"])?;
+    git(
+        dir,
+        &["symbolic-ref", "HEAD", &format!("refs/heads/{BRANCH}")],
+    )?;
+    // commits need an author, which isn't set up on every machine
+    if git(dir, &["config", "user.email"]).is_err() {
+        git(dir, &["config", "user.name", "pls"])?;
+        git(dir, &["config", "user.email", "pls@localhost"])?;
+    }
+    fs::write(dir.join(".gitignore"), format!("*\n!.gitignore\n!{FILE}\n"))
+        .map_err(|e| format!("Failed to write .gitignore: {e}"))?;
+    git(dir, &["remote", "add", "origin", &remote]).map(drop)
+}
+
+/// Whether the config directory is synced with a remote.
+pub fn is_set_up(dir: &Path) -> bool {
+    dir.join(".git").exists()
+}
+
+/// Commit the tasks, pull the changes made on other machines, merging them task by task, and
+/// push the result. The change to the local tasks is recorded in the journal.
+pub fn sync(db: &mut dyn Store, dir: &Path, command: &str) -> Result<Synced, String> {
+    if !is_set_up(dir) {
+        return Err("sync isn't set up, run `pls sync init <REMOTE>` first".to_string());
+    }
+    // the synced file would keep them in plain text
+    if db.key().is_some() {
+        return Err("the tasks are encrypted, run `pls decrypt` to sync them".to_string());
+    }
+    let remote = git(dir, &["remote", "get-url", "origin"])?;
+    let local = db.tasks();
+    commit(dir, &local, "Update tasks")?;
+
+    git(dir, &["fetch", "--quiet", "origin"])?;
+    let tracking = format!("refs/remotes/origin/{BRANCH}");
+    let merged = if git(dir, &["rev-parse", "--verify", "--quiet", &tracking]).is_ok() {
+        let head = git(dir, &["rev-parse", "HEAD"])?;
+        match merge_remote(dir, &local, &tracking, &remote) {
+            Ok(merged) => merged,
+            Err(e) => {
+                // back to the local tasks, so that the next sync merges them again
+                drop(git(dir, &["reset", "--quiet", "--hard", &head]));
+                return Err(e);
+            }
+        }
+    } else {
+        local.clone()
+    };
+    // saved before pushing: if the push fails, the merge commit is already there and the next
+    // sync takes these tasks for the local ones, which must include what was merged
+    if merged != local {
+        journal::save_tasks(db, command, &local, &merged);
+        merge::skip_ids(db, &merged).map_err(|e| format!("Failed to set task id: {e}"))?;
+    }
+    git(
+        dir,
+        &[
+            "push",
+            "--quiet",
+            "origin",
+            &format!("HEAD:refs/heads/{BRANCH}"),
+        ],
+    )
+    .map_err(|e| format!("{e}. The tasks were merged here, run pls sync again to push them"))?;
+
+    Ok(Synced {
+        remote,
+        before: local,
+        after: merged,
+    })
+}
+
+// merge the tasks of the remote branch into the local ones, task by task, and commit the result
+fn merge_remote(
+    dir: &Path,
+    local: &[Task],
+    tracking: &str,
+    remote: &str,
+) -> Result<Vec<Task>, String> {
+    let theirs = read(dir, tracking)?;
+    // the last version both sides had, if they have ever synced
+    let base = match git(dir, &["merge-base", "HEAD", tracking]) {
+        Ok(commit) => read(dir, &commit)?,
+        Err(_) => Vec::new(),
+    };
+    // their tasks don't have tombstones, but the base tells which tasks were removed
+    let (local_removed, pushed_removed) =
+        (merge::removed(&base, local), merge::removed(&base, &theirs));
+    let local = Side {
+        tasks: local,
+        tombstones: &local_removed,
+    };
+    let pushed = Side {
+        tasks: &theirs,
+        tombstones: &pushed_removed,
+    };
+    // merged into the pushed tasks rather than the other way around, so that a local task with
+    // the id of a pushed one gets a new id here, before any other machine has it: pushed tasks
+    // keep their ids, and every machine ends up with the same ones
+    let merged = merge::merge(&base, &[], &pushed, &local, Strategy::Newest).tasks;
+    if git(dir, &["merge-base", "--is-ancestor", "HEAD", tracking]).is_ok() {
+        git(dir, &["merge", "--quiet", "--ff-only", tracking])?;
+        commit(dir, &merged, "Update tasks")?;
+    } else if git(dir, &["merge-base", "--is-ancestor", tracking, "HEAD"]).is_err() {
+        // git only records the merge, the file is merged by task rather than by line
+        git(
+            dir,
+            &[
+                "merge",
+                "--quiet",
+                "--no-commit",
+                "--no-ff",
+                "--strategy=ours",
+                "--allow-unrelated-histories",
+                tracking,
+            ],
+        )?;
+        commit(dir, &merged, &format!("Merge tasks from {remote}"))?;
+    }
+    Ok(merged)
+}
+
+// write the tasks to the synced file and commit them, if they changed. a merge in progress is
+// committed even if they didn't
+fn commit(dir: &Path, tasks: &[Task], message: &str) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(tasks)
+        .map_err(|e| format!("Failed to serialize tasks: {e}"))?;
+    fs::write(dir.join(FILE), json + "\n").map_err(|e| format!("Failed to write {FILE}: {e}"))?;
+    git(dir, &["add", FILE, ".gitignore"])?;
+    let merging = git(dir, &["rev-parse", "--verify", "--quiet", "MERGE_HEAD"]).is_ok();
+    if merging || git(dir, &["diff", "--cached", "--quiet"]).is_err() {
+        git(dir, &["commit", "--quiet", "--no-verify", "-m", message])?;
+    }
+    Ok(())
+}
+
+// the tasks in the synced file of a commit, none if the file wasn't there
+fn read(dir: &Path, commit: &str) -> Result<Vec<Task>, String> {
+    git(dir, &["show", &format!("{commit}:{FILE}")]).map_or_else(
+        |_| Ok(Vec::new()),
+        |json| serde_json::from_str(&json).map_err(|e| format!("Invalid {FILE} in {commit}: {e}")),
+    )
+}
+
+// run git in the directory, returning what it printed
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("Failed to run git: {e}"))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(format!("git {} failed: {error}", args[0]))
+    }
+}
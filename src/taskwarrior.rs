@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::{
+    ical::{format_utc, parse_time},
+    priority::Priority,
+    recur::Recurrence,
+    Note, Task,
+};
+
+// fields of `task export` that Taskwarrior works out by itself, so there is nothing to map
+const IGNORED: [&str; 4] = ["id", "urgency", "mask", "imask"];
+
+// a task as `task export` writes it and `task import` reads it. times are UTC, ex:
+// "20240517T093000Z"
+#[derive(Serialize, Deserialize)]
+struct TwTask {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uuid: Option<String>,
+    description: String,
+    status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    end: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    due: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    priority: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recur: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<Annotation>,
+    // uuids of the tasks this one waits for. older versions of Taskwarrior write a comma
+    // separated string instead of a list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    depends: Option<Value>,
+    // the template a recurring task's instance was made from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Annotation {
+    #[serde(default)]
+    entry: Option<String>,
+    description: String,
+}
+
+/// The UUID of a task in Taskwarrior. Tasks that didn't come from Taskwarrior get one made up
+/// from their id and creation time, so that they get the same one on every export.
+pub fn uuid(task: &Task) -> String {
+    task.uid
+        .clone()
+        .filter(|uid| is_uuid(uid))
+        .unwrap_or_else(|| {
+            // the bits of a version 8 (custom) UUID
+            let time = u64::try_from(task.created_at.unwrap_or_default()).unwrap_or_default();
+            format!(
+                "{:08x}-{:04x}-8{:03x}-{:04x}-{:012x}",
+                (time >> 16) & 0xffff_ffff,
+                time & 0xffff,
+                (task.id >> 48) & 0xfff,
+                0x8000 | ((task.id >> 32) & 0x3fff),
+                task.id & 0xffff_ffff_ffff
+            )
+        })
+}
+
+/// Write tasks as JSON that `task import` reads. Recurring tasks become recurrence templates,
+/// and a task with subtasks depends on them.
+pub fn export(tasks: &[Task], now: OffsetDateTime) -> Result<String, String> {
+    let time = |timestamp: Option<i64>| timestamp.and_then(format_utc);
+    let exported = tasks
+        .iter()
+        .map(|task| {
+            let recurring = !task.completed && task.due.is_some() && task.recurrence.is_some();
+            let subtasks = tasks
+                .iter()
+                .filter(|t| t.parent == Some(task.id))
+                .map(|t| Value::String(uuid(t)))
+                .collect::<Vec<_>>();
+            TwTask {
+                uuid: Some(uuid(task)),
+                description: task.title.clone(),
+                status: match (task.completed, recurring) {
+                    (true, _) => "completed",
+                    (false, true) => "recurring",
+                    (false, false) => "pending",
+                }
+                .to_string(),
+                entry: time(task.created_at),
+                modified: time(task.updated_at),
+                end: time(task.completed_at.filter(|_| task.completed)),
+                due: time(task.due),
+                project: task.project.clone(),
+                tags: task.tags.clone(),
+                priority: task.priority.map(|p| letter(p).to_string()),
+                recur: task.recurrence.filter(|_| recurring).map(recur),
+                annotations: task
+                    .notes
+                    .iter()
+                    .map(|note| Annotation {
+                        entry: format_utc(note.created_at.unwrap_or_else(|| now.unix_timestamp())),
+                        description: note.text.clone(),
+                    })
+                    .collect(),
+                depends: (!subtasks.is_empty()).then_some(Value::Array(subtasks)),
+                parent: None,
+                other: Map::new(),
+            }
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string_pretty(&exported)
+        .map(|json| json + "\n")
+        .map_err(|e| format!("Failed to export tasks: {e}"))
+}
+
+/// Read the output of `task export`, either a JSON list or one task per line. Returns the tasks,
+/// with ids counting up from 1, and the fields pls has no place for with how many tasks had
+/// each. Deleted tasks are left out, and so are the pending instances of a recurring task,
+/// which comes in once from its template.
+pub fn import(
+    text: &str,
+    offset: UtcOffset,
+) -> Result<(Vec<Task>, BTreeMap<String, usize>), String> {
+    let exported = if text.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<TwTask>>(text)
+            .map_err(|e| format!("Invalid Taskwarrior export: {e}"))?
+    } else {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                serde_json::from_str::<TwTask>(line.trim().trim_end_matches(','))
+                    .map_err(|e| format!("Invalid Taskwarrior export on line {}: {e}", number + 1))
+            })
+            .collect::<Result<_, _>>()?
+    };
+    let time = |text: &Option<String>| text.as_deref().and_then(|t| parse_time(t, "", offset));
+
+    let templates = exported
+        .iter()
+        .filter(|t| t.status == "recurring")
+        .filter_map(|t| t.uuid.as_deref())
+        .collect::<HashSet<_>>();
+    let is_instance = |t: &TwTask| t.parent.as_deref().is_some_and(|p| templates.contains(p));
+    // a recurring task is next due when its earliest pending instance is
+    let mut next_due = HashMap::new();
+    for instance in exported
+        .iter()
+        .filter(|t| t.status == "pending" && is_instance(t))
+    {
+        if let (Some(parent), Some(due)) = (instance.parent.as_deref(), time(&instance.due)) {
+            let next = next_due.entry(parent).or_insert(due);
+            *next = due.min(*next);
+        }
+    }
+
+    let mut tasks = Vec::new();
+    let mut depends = Vec::new();
+    let mut unmapped = BTreeMap::new();
+    let mut report = |field: &str| *unmapped.entry(field.to_string()).or_insert(0) += 1;
+    for tw in &exported {
+        if tw.status == "deleted" {
+            report("status:deleted");
+            continue;
+        }
+        if tw.status != "completed" && is_instance(tw) {
+            continue;
+        }
+        let mut task = Task {
+            id: tasks.len() as u64 + 1,
+            title: tw.description.trim().to_string(),
+            completed: tw.status == "completed",
+            uid: tw.uuid.clone(),
+            due: tw
+                .uuid
+                .as_deref()
+                .and_then(|uuid| next_due.get(uuid).copied())
+                .or_else(|| time(&tw.due)),
+            project: tw.project.as_ref().map(|p| p.to_lowercase()),
+            created_at: time(&tw.entry),
+            updated_at: time(&tw.modified).or_else(|| time(&tw.entry)),
+            ..Task::default()
+        };
+        if task.title.is_empty() {
+            report("description");
+            continue;
+        }
+        if task.completed {
+            task.completed_at = time(&tw.end).or(task.updated_at);
+        }
+        for tag in &tw.tags {
+            let tag = tag.to_lowercase();
+            if !task.tags.contains(&tag) {
+                task.tags.push(tag);
+            }
+        }
+        match tw.priority.as_deref().map(str::trim) {
+            None | Some("") => {}
+            Some("H") => task.priority = Some(Priority::High),
+            Some("M") => task.priority = Some(Priority::Medium),
+            Some("L") => task.priority = Some(Priority::Low),
+            Some(_) => report("priority"),
+        }
+        // completed instances of a recurring task are done and don't come back themselves
+        if let Some(rule) = tw.recur.as_deref().filter(|_| !is_instance(tw)) {
+            task.recurrence = parse_recur(rule, task.due, offset);
+            if task.recurrence.is_none() {
+                report("recur");
+            }
+        }
+        task.notes = tw
+            .annotations
+            .iter()
+            .map(|annotation| Note {
+                text: annotation.description.clone(),
+                created_at: time(&annotation.entry),
+            })
+            .collect();
+        for field in tw.other.keys().filter(|f| !IGNORED.contains(&f.as_str())) {
+            report(field);
+        }
+        depends.push((task.id, dependencies(tw.depends.as_ref())));
+        tasks.push(task);
+    }
+
+    // the tasks a task depends on become its subtasks, unless they already have a parent or
+    // it would make a loop
+    let ids = tasks
+        .iter()
+        .filter_map(|t| Some((t.uid.clone()?, t.id)))
+        .collect::<HashMap<_, _>>();
+    for (id, uuids) in depends {
+        for uuid in uuids {
+            let Some(&child) = ids.get(&uuid) else {
+                report("depends");
+                continue;
+            };
+            let mut ancestor = Some(id);
+            while let Some(a) = ancestor.filter(|a| *a != child) {
+                ancestor = tasks.iter().find(|t| t.id == a).and_then(|t| t.parent);
+            }
+            match tasks.iter_mut().find(|t| t.id == child) {
+                Some(task) if task.parent.is_none() && ancestor.is_none() => {
+                    task.parent = Some(id);
+                }
+                _ => report("depends"),
+            }
+        }
+    }
+    Ok((tasks, unmapped))
+}
+
+fn dependencies(depends: Option<&Value>) -> Vec<String> {
+    match depends {
+        Some(Value::String(uuids)) => uuids
+            .split(',')
+            .map(str::trim)
+            .filter(|uuid| !uuid.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Some(Value::Array(uuids)) => uuids
+            .iter()
+            .filter_map(|uuid| uuid.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+const fn letter(priority: Priority) -> char {
+    match priority {
+        Priority::High => 'H',
+        Priority::Medium => 'M',
+        Priority::Low => 'L',
+    }
+}
+
+fn recur(recurrence: Recurrence) -> String {
+    match recurrence {
+        Recurrence::Daily => "daily",
+        Recurrence::Weekday => "weekdays",
+        // Taskwarrior repeats from the due date, which is already on the right day
+        Recurrence::Weekly(_) => "weekly",
+        Recurrence::Monthly(_) => "monthly",
+    }
+    .to_string()
+}
+
+// the periods pls can represent. weekly and monthly tasks recur on the day they are due
+fn parse_recur(rule: &str, due: Option<i64>, offset: UtcOffset) -> Option<Recurrence> {
+    let due = || {
+        OffsetDateTime::from_unix_timestamp(due?)
+            .ok()
+            .map(|due| due.to_offset(offset))
+    };
+    match rule.trim().to_lowercase().as_str() {
+        "daily" | "day" | "1d" | "1day" | "p1d" => Some(Recurrence::Daily),
+        "weekdays" => Some(Recurrence::Weekday),
+        "weekly" | "week" | "1w" | "1wk" | "1week" | "p1w" | "7d" | "p7d" => {
+            due().map(|due| Recurrence::Weekly(due.weekday()))
+        }
+        "monthly" | "month" | "1mo" | "1month" | "p1m" => {
+            due().map(|due| Recurrence::Monthly(due.day()))
+        }
+        _ => None,
+    }
+}
+
+fn is_uuid(text: &str) -> bool {
+    text.len() == 36
+        && text.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
@@ -39,3 +39,1934 @@ fn test_tasks() {
     assert_eq!(get_tasks(&db), tasks);
     assert!(!get_tasks(&db).is_empty())
 }
+
+#[test]
+fn test_parse_due() {
+    use crate::due::parse_due;
+    use time::macros::datetime;
+
+    // a wednesday afternoon
+    let now = datetime!(2024-05-15 14:00 +2);
+    let parse = |s| {
+        parse_due(s, now).map(|t| {
+            time::OffsetDateTime::from_unix_timestamp(t)
+                .unwrap()
+                .to_offset(now.offset())
+        })
+    };
+
+    assert_eq!(parse("tomorrow"), Ok(datetime!(2024-05-16 23:59 +2)));
+    assert_eq!(parse("in 3 days"), Ok(datetime!(2024-05-18 23:59 +2)));
+    assert_eq!(parse("in 2 hours"), Ok(datetime!(2024-05-15 16:00 +2)));
+    assert_eq!(parse("next friday 5pm"), Ok(datetime!(2024-05-17 17:00 +2)));
+    assert_eq!(parse("wed at 9:30am"), Ok(datetime!(2024-05-22 09:30 +2)));
+    assert_eq!(parse("2024-06-01"), Ok(datetime!(2024-06-01 23:59 +2)));
+    assert_eq!(
+        parse("2024-06-01T08:15"),
+        Ok(datetime!(2024-06-01 08:15 +2))
+    );
+    assert_eq!(parse("noon"), Ok(datetime!(2024-05-16 12:00 +2)));
+    assert!(parse("someday").is_err());
+    assert!(parse("in three days").is_err());
+    // too far off to be a date
+    assert!(parse("in 99999999999 weeks").is_err());
+    assert!(parse("in 9223372036854775807 minutes").is_err());
+}
+
+#[test]
+fn test_find_task() {
+    use crate::find_task;
+
+    let mut tasks = vec![Task::new(&"first".into()), Task::new(&"second".into())];
+    tasks[0].id = 1;
+    tasks[1].id = 5;
+
+    // only ids are matched, never positions
+    assert_eq!(find_task(&tasks, Some(&"5".into())), Some(1));
+    assert_eq!(find_task(&tasks, Some(&"2".into())), None);
+    assert_eq!(find_task(&tasks, Some(&"1".into())), Some(0));
+    assert_eq!(find_task(&tasks, None), Some(0));
+    assert_eq!(find_task(&tasks, Some(&"9".into())), None);
+    assert_eq!(find_task(&tasks, Some(&"two".into())), None);
+    assert_eq!(find_task(&[], None), None);
+}
+
+#[test]
+fn test_priority_sort() {
+    use crate::{priority::strip_inline, priority::Priority, sort_tasks};
+
+    assert_eq!(
+        strip_inline("ship report !!!"),
+        ("ship report".to_string(), Some(Priority::High))
+    );
+    assert_eq!(strip_inline("hello!"), ("hello!".to_string(), None));
+
+    // fields in the order Task declares them, as clippy::pedantic wants
+    let task = |id, priority, due, completed| Task {
+        id,
+        completed,
+        due,
+        priority,
+        ..Default::default()
+    };
+    let tasks = vec![
+        task(1, None, None, false),
+        task(2, Some(Priority::Low), None, false),
+        task(3, Some(Priority::High), None, true),
+        task(4, Some(Priority::Low), Some(10), false),
+        task(5, Some(Priority::High), None, false),
+        task(6, None, None, false),
+    ];
+    let order = sort_tasks(&tasks).iter().map(|t| t.id).collect::<Vec<_>>();
+    assert_eq!(order, vec![5, 4, 2, 1, 6, 3]);
+}
+
+#[test]
+fn test_filter() {
+    use crate::filter::{strip_tags, Filter};
+
+    let (title, tags, project) = strip_tags("deploy +work the api @backend +urgent");
+    assert_eq!(title, "deploy the api");
+    assert_eq!(tags, vec!["work", "urgent"]);
+    assert_eq!(project.as_deref(), Some("backend"));
+
+    let task = Task {
+        tags,
+        project,
+        ..Default::default()
+    };
+    let filter = |args: &[&str]| {
+        Filter::parse(
+            args.iter()
+                .map(|a| (*a).to_string())
+                .collect::<Vec<_>>()
+                .iter(),
+        )
+        .unwrap()
+    };
+    assert!(filter(&[]).matches(&task));
+    assert!(filter(&["+work", "@backend"]).matches(&task));
+    assert!(filter(&["@frontend", "@backend"]).matches(&task));
+    assert!(!filter(&["+work", "+home"]).matches(&task));
+    assert!(!filter(&["@home"]).matches(&task));
+    assert!(Filter::parse(&["work".to_string()]).is_err());
+}
+
+#[test]
+fn test_apply_edit() {
+    use crate::{edit::apply_edit, priority::Priority};
+
+    let task = Task {
+        id: 7,
+        title: "typo tsak".into(),
+        tags: vec!["work".into()],
+        ..Default::default()
+    };
+    let edited = apply_edit(
+        &task,
+        "title = \"typo task\"\ncompleted = true\npriority = \"med\"\ntags = [\"home\"]\n",
+    )
+    .unwrap();
+    assert_eq!(edited.id, 7);
+    assert_eq!(edited.title, "typo task");
+    assert!(edited.completed);
+    assert_eq!(edited.priority, Some(Priority::Medium));
+    assert_eq!(edited.tags, vec!["home"]);
+
+    // edits that can't be stored are rejected
+    assert!(apply_edit(&task, "title = \"\"\ncompleted = false\n").is_err());
+    assert!(apply_edit(&task, "title = \"x\"\ncompleted = \"no\"\n").is_err());
+    assert!(apply_edit(&task, "title = \"x\"\ncompleted = false\nid = 1\n").is_err());
+    assert!(apply_edit(
+        &task,
+        "title = \"x\"\ncompleted = false\ndue = \"someday\"\n"
+    )
+    .is_err());
+    assert!(apply_edit(&task, "not toml").is_err());
+}
+
+#[test]
+fn test_selector() {
+    use crate::selector::select;
+
+    let tasks = [1, 2, 4, 5, 6, 9]
+        .into_iter()
+        .map(|id| Task {
+            id,
+            tags: if id % 2 == 0 {
+                vec!["even".into()]
+            } else {
+                vec![]
+            },
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let select = |s: &str| select(&tasks, &[s.to_string()]);
+
+    assert_eq!(select("2,4-6"), Ok(vec![1, 2, 3, 4]));
+    assert_eq!(select("9 last"), Ok(vec![5]));
+    assert_eq!(select("+even"), Ok(vec![1, 2, 4]));
+    assert_eq!(select("1,+even"), Ok(vec![0, 1, 2, 4]));
+    assert_eq!(select("all").map(|i| i.len()), Ok(6));
+    assert_eq!(select(""), Ok(vec![0]));
+    assert!(select("10-20").is_err());
+    assert!(select("42").is_err());
+    // a single id and a range of one id are resolved the same way
+    assert_eq!(select("4"), select("4-4"));
+    assert!(select("3").is_err() && select("3-3").is_err());
+    assert!(select("1,10-20").is_err());
+    assert!(select("2,x").is_err());
+    assert!(select("+odd").is_err());
+}
+
+#[test]
+#[sealed_test]
+fn test_journal() {
+    use crate::journal::{redo, revert, save_tasks};
+
+    let mut db = PickleDb::load_or_new(
+        "test",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to create db");
+    db.set("history-size", &"2".to_string()).unwrap();
+
+    let one = vec![Task::new(&"one".into())];
+    let two = vec![Task::new(&"one".into()), Task::new(&"two".into())];
+    save_tasks(&mut db, "add one", &[], &one);
+    save_tasks(&mut db, "add two", &one, &two);
+    save_tasks(&mut db, "rm --all", &two, &[]);
+    assert!(get_tasks(&db).is_empty());
+
+    // only two operations are kept, so the first add can't be reverted
+    assert_eq!(revert(&mut db, 5).len(), 2);
+    assert_eq!(get_tasks(&db), one);
+    assert_eq!(redo(&mut db, 1)[0].command, "add two");
+    assert_eq!(get_tasks(&db), two);
+
+    // a new change clears what could be redone
+    save_tasks(&mut db, "add one", &two, &one);
+    assert!(redo(&mut db, 1).is_empty());
+    assert_eq!(revert(&mut db, 1).len(), 1);
+    assert_eq!(get_tasks(&db), two);
+}
+
+#[test]
+fn test_recurrence() {
+    use crate::recur::Recurrence;
+    use time::macros::{date, datetime};
+
+    let parse = |s: &str| s.parse::<Recurrence>().unwrap();
+    // 2024-05-17 is a friday
+    assert_eq!(
+        parse("daily").next_date(date!(2024 - 05 - 17)),
+        date!(2024 - 05 - 18)
+    );
+    assert_eq!(
+        parse("weekday").next_date(date!(2024 - 05 - 17)),
+        date!(2024 - 05 - 20)
+    );
+    assert_eq!(
+        parse("weekly:mon").next_date(date!(2024 - 05 - 17)),
+        date!(2024 - 05 - 20)
+    );
+    assert_eq!(
+        parse("weekly:fri").first_date(date!(2024 - 05 - 17)),
+        date!(2024 - 05 - 17)
+    );
+    assert_eq!(
+        parse("monthly:1").next_date(date!(2024 - 05 - 17)),
+        date!(2024 - 06 - 01)
+    );
+    assert_eq!(
+        parse("monthly:31").next_date(date!(2024 - 01 - 31)),
+        date!(2024 - 02 - 29)
+    );
+    assert_eq!(
+        parse("monthly:31").next_date(date!(2024 - 12 - 31)),
+        date!(2025 - 01 - 31)
+    );
+    assert_eq!(parse("weekly:wednesday").to_string(), "weekly:wed");
+    assert!("monthly:32".parse::<Recurrence>().is_err());
+    assert!("hourly".parse::<Recurrence>().is_err());
+
+    // occurrences that are already in the past are skipped
+    assert_eq!(
+        parse("daily").next_due(
+            datetime!(2024-05-10 09:00 UTC),
+            datetime!(2024-05-17 12:00 UTC)
+        ),
+        datetime!(2024-05-18 09:00 UTC)
+    );
+}
+
+#[test]
+#[sealed_test]
+fn test_complete_recurring() {
+    use crate::{complete_recurring, recur::Recurrence};
+
+    let mut db = PickleDb::load_or_new(
+        "test",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to create db");
+
+    db.set("task-id", &2).unwrap();
+    let mut tasks = vec![Task {
+        id: 1,
+        title: "standup".into(),
+        due: Some(0),
+        recurrence: Some(Recurrence::Daily),
+        ..Default::default()
+    }];
+    complete_recurring(&mut db, &mut tasks, 0);
+    assert_eq!(tasks.len(), 2);
+    // the recurring task stays pending and is due again in the future
+    assert!(!tasks[0].completed);
+    assert!(tasks[0].due.unwrap() > crate::get_time().unix_timestamp());
+    // the completion is logged as a separate, non-recurring task
+    assert!(tasks[1].completed);
+    assert_eq!(tasks[1].title, "standup");
+    assert_eq!(tasks[1].recurrence, None);
+    assert_eq!(tasks[1].id, 2);
+}
+
+#[test]
+fn test_subtasks() {
+    use crate::{subtasks, tree_tasks};
+
+    let task = |id, parent| Task {
+        id,
+        parent,
+        ..Default::default()
+    };
+    // 4 is a subtask of 2, which is a subtask of 1. 5's parent no longer exists
+    let tasks = vec![
+        task(1, None),
+        task(2, Some(1)),
+        task(3, None),
+        task(4, Some(2)),
+        task(5, Some(9)),
+        task(6, Some(1)),
+    ];
+    let tree = tree_tasks(&tasks)
+        .into_iter()
+        .map(|(depth, t)| (depth, t.id))
+        .collect::<Vec<_>>();
+    assert_eq!(tree, vec![(0, 1), (1, 2), (2, 4), (1, 6), (0, 3), (0, 5)]);
+    assert_eq!(subtasks(&tasks, 1), vec![1, 5, 3]);
+    assert!(subtasks(&tasks, 3).is_empty());
+
+    // parents that point at each other are still shown, the first of them at the top level
+    let tasks = vec![
+        task(1, None),
+        task(2, Some(3)),
+        task(3, Some(2)),
+        task(4, Some(3)),
+    ];
+    let tree = tree_tasks(&tasks)
+        .into_iter()
+        .map(|(depth, t)| (depth, t.id))
+        .collect::<Vec<_>>();
+    assert_eq!(tree, vec![(0, 1), (0, 2), (1, 3), (2, 4)]);
+    assert_eq!(subtasks(&tasks, 2), vec![2, 3]);
+}
+
+#[test]
+#[sealed_test]
+fn test_timestamps() {
+    use crate::due::format_age;
+    use time::macros::datetime;
+
+    // tasks saved before timestamps existed still load
+    std::fs::write(
+        "test",
+        r#"[{"tasks":"[{\"title\":\"old task\",\"completed\":true}]"},{}]"#,
+    )
+    .unwrap();
+    let db = PickleDb::load_or_new(
+        "test",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to load db");
+    let tasks = get_tasks(&db);
+    assert_eq!(tasks[0].title, "old task");
+    assert_eq!(tasks[0].created_at, None);
+    assert_eq!(tasks[0].completed_at, None);
+
+    let task = Task::new(&"new task".into());
+    assert!(task.created_at.is_some());
+    let done = task.make_complete();
+    assert!(done.completed_at.is_some());
+    assert_eq!(done.make_incomplete().completed_at, None);
+
+    let now = datetime!(2024-05-17 12:00 UTC);
+    let ago = |seconds: i64| format_age(now.unix_timestamp() - seconds, now);
+    assert_eq!(ago(5), "just now");
+    assert_eq!(ago(90), "1m ago");
+    assert_eq!(ago(3 * 3_600), "3h ago");
+    assert_eq!(ago(3 * 86_400), "3d ago");
+    assert_eq!(ago(65 * 86_400), "2mo ago");
+    assert_eq!(ago(800 * 86_400), "2y ago");
+}
+
+#[test]
+#[sealed_test]
+fn test_store() {
+    use crate::{
+        journal::{revert, save_tasks},
+        store::Store,
+    };
+
+    let mut json = PickleDb::load_or_new(
+        "test",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to create db");
+    let store: &mut dyn Store = &mut json;
+    assert_eq!(store.quote_cursor(), None);
+    store.set_quote_cursor(3).unwrap();
+    store.cache_weather("sunny", 100).unwrap();
+    assert_eq!(store.quote_cursor(), Some(3));
+    assert_eq!(store.cached_weather().as_deref(), Some("sunny"));
+    assert_eq!(store.weather_timestamp(), Some(100));
+    store.set("history-size", "5").unwrap();
+    assert_eq!(store.setting("history-size").as_deref(), Some("5"));
+    store.rem("history-size").unwrap();
+    assert!(!store.exists("history-size"));
+
+    let tasks = vec![Task::new(&"one".into()), Task::new(&"two".into())];
+    save_tasks(store, "add", &[], &tasks);
+    assert_eq!(store.tasks(), tasks);
+
+    #[cfg(feature = "sqlite")]
+    {
+        let mut sqlite = crate::store::sqlite::SqliteStore::open(std::path::Path::new(":memory:"))
+            .expect("failed to open sqlite");
+        crate::store::copy_all(store, &mut sqlite).unwrap();
+        assert_eq!(sqlite.tasks(), tasks);
+        let sqlite: &mut dyn Store = &mut sqlite;
+        assert_eq!(sqlite.quote_cursor(), Some(3));
+        assert_eq!(sqlite.cached_weather().as_deref(), Some("sunny"));
+
+        save_tasks(sqlite, "rm", &tasks, &tasks[1..]);
+        assert_eq!(sqlite.tasks(), &tasks[1..]);
+        assert_eq!(revert(sqlite, 1).len(), 1);
+        assert_eq!(sqlite.tasks(), tasks);
+        // a row that can't be read as a task is kept when the tasks are saved
+        let path = std::path::Path::new("tasks.db");
+        let mut sqlite =
+            crate::store::sqlite::SqliteStore::open(path).expect("failed to open sqlite");
+        sqlite.set_tasks(&tasks).unwrap();
+        let conn = rusqlite::Connection::open(path).unwrap();
+        conn.execute("INSERT INTO tasks VALUES (99, '{\"id\": \"x\"}')", [])
+            .unwrap();
+        assert_eq!(sqlite.tasks(), tasks);
+        sqlite.set_tasks(&tasks[1..]).unwrap();
+        assert_eq!(sqlite.tasks(), &tasks[1..]);
+        let rows = conn
+            .query_row("SELECT COUNT(*) FROM tasks", [], |row| {
+                row.get::<_, usize>(0)
+            })
+            .unwrap();
+        assert_eq!(rows, tasks.len());
+        // a transaction holds the write lock from the start, even with one nested in it
+        conn.busy_timeout(std::time::Duration::ZERO).unwrap();
+        sqlite.begin().unwrap();
+        assert!(conn.execute("DELETE FROM tasks", []).is_err());
+        sqlite.set_tasks(&tasks).unwrap();
+        sqlite.commit().unwrap();
+        assert!(conn.execute("DELETE FROM kv", []).is_ok());
+        assert_eq!(sqlite.tasks(), tasks);
+        let sqlite: &mut dyn Store = &mut sqlite;
+        sqlite.rem("tasks").unwrap();
+        assert!(sqlite.tasks().is_empty());
+    }
+    assert_eq!(revert(store, 1).len(), 1);
+    assert!(store.tasks().is_empty());
+}
+
+#[test]
+#[sealed_test]
+fn test_migrate() {
+    use crate::migrate::{migrate, SCHEMA_VERSION};
+    use crate::store::Store;
+
+    // pls.json files written by pls 0.2.0
+    let fixtures = [
+        r#"[{"name":"\"Sam\"","weather":"false","quote":"4","tasks":"[{\"title\":\"buy milk\",\"completed\":false},{\"title\":\"call mum\",\"completed\":true},{\"title\":\"write report\",\"completed\":false}]"},{}]"#,
+        r#"[{"name":"\"Alex\"","weather":"true","weather-cached":"\" Sunny ☀️ +21°C\"","weather-timestamp":"1712000000","tasks":"[]"},{}]"#,
+    ];
+    let mut backups = Vec::new();
+    for (n, fixture) in fixtures.iter().enumerate() {
+        let path = format!("fixture-{n}.json");
+        std::fs::write(&path, fixture).unwrap();
+        let mut json = PickleDb::load_or_new(
+            &path,
+            pickledb::PickleDbDumpPolicy::NeverDump,
+            pickledb::SerializationMethod::Json,
+        )
+        .expect("failed to load fixture");
+        let db: &mut dyn Store = &mut json;
+        let old_tasks = db.tasks();
+
+        let upgrade = migrate(db, std::path::Path::new("backups"))
+            .unwrap()
+            .expect("0.2.0 databases need upgrading");
+        assert_eq!(upgrade.from, 1);
+        assert_eq!(db.get::<u32>("schema_version"), Some(SCHEMA_VERSION));
+
+        // titles and statuses are kept, and every task gets its own id
+        let tasks = db.tasks();
+        assert_eq!(tasks.len(), old_tasks.len());
+        for (task, old) in tasks.iter().zip(&old_tasks) {
+            assert_eq!((&task.title, task.completed), (&old.title, old.completed));
+        }
+        let mut ids = tasks.iter().map(|t| t.id).collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), tasks.len());
+        assert!(ids.iter().all(|id| *id != 0));
+
+        // the backup holds the database as it was before the upgrade
+        let backup = PickleDb::load_read_only(&upgrade.backup, pickledb::SerializationMethod::Json)
+            .expect("failed to load backup");
+        assert_eq!(get_tasks(&backup), old_tasks);
+        assert_eq!(backup.get::<u32>("schema_version"), None);
+        backups.push(upgrade.backup);
+
+        // an up to date database is left alone
+        assert!(migrate(db, std::path::Path::new("backups"))
+            .unwrap()
+            .is_none());
+    }
+
+    // upgrades in the same second don't overwrite each other's backups
+    assert_ne!(backups[0], backups[1]);
+    let backup = PickleDb::load_read_only(&backups[0], pickledb::SerializationMethod::Json)
+        .expect("failed to load backup");
+    assert_eq!(get_tasks(&backup)[0].title, "buy milk");
+
+    // a new database starts at the current version, and newer databases are refused
+    let mut json = PickleDb::new_json("new.json", pickledb::PickleDbDumpPolicy::NeverDump);
+    let db: &mut dyn Store = &mut json;
+    assert!(migrate(db, std::path::Path::new("backups"))
+        .unwrap()
+        .is_none());
+    assert_eq!(db.get::<u32>("schema_version"), Some(SCHEMA_VERSION));
+    db.set("schema_version", &(SCHEMA_VERSION + 1)).unwrap();
+    assert!(migrate(db, std::path::Path::new("backups")).is_err());
+}
+
+#[test]
+#[sealed_test]
+fn test_json_store_locking() {
+    use crate::{
+        journal::save_tasks,
+        store::{JsonStore, Store},
+    };
+    use std::path::Path;
+
+    // a store opened before another process wrote keeps that process's changes
+    let mut foreground = JsonStore::open(Path::new("pls.json")).unwrap();
+    let mut background = JsonStore::open(Path::new("pls.json")).unwrap();
+    let tasks = vec![Task::new(&"task".into())];
+    (&mut foreground as &mut dyn Store)
+        .set_tasks(&tasks)
+        .unwrap();
+    (&mut background as &mut dyn Store)
+        .cache_weather("sunny", 100)
+        .unwrap();
+    let reopened = JsonStore::open(Path::new("pls.json")).unwrap();
+    assert_eq!(reopened.tasks(), tasks);
+    assert_eq!(
+        (&reopened as &dyn Store).cached_weather().as_deref(),
+        Some("sunny")
+    );
+
+    // the file is still readable by pickledb, and no temporary files are left behind
+    let db = PickleDb::load_read_only("pls.json", pickledb::SerializationMethod::Json).unwrap();
+    assert_eq!(get_tasks(&db), tasks);
+    let leftovers = std::fs::read_dir(".")
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+        .count();
+    assert_eq!(leftovers, 0);
+
+    // a new file can only be read by its owner, and a file keeps its permissions when written
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = || std::fs::metadata("pls.json").unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(), 0o600);
+        std::fs::set_permissions("pls.json", std::fs::Permissions::from_mode(0o640)).unwrap();
+        (&mut foreground as &mut dyn Store)
+            .cache_weather("rainy", 200)
+            .unwrap();
+        assert_eq!(mode(), 0o640);
+    }
+
+    // read-modify-write transactions from several processes at once never lose an update
+    let threads = (0..4)
+        .map(|_| {
+            std::thread::spawn(|| {
+                let mut store = JsonStore::open(Path::new("pls.json")).unwrap();
+                let store: &mut dyn Store = &mut store;
+                for _ in 0..10 {
+                    store.begin().unwrap();
+                    let count = store.get::<u32>("count").unwrap_or_default();
+                    store.set("count", &(count + 1)).unwrap();
+                    store.commit().unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let store = JsonStore::open(Path::new("pls.json")).unwrap();
+    assert_eq!((&store as &dyn Store).get::<u32>("count"), Some(40));
+
+    // two commands that read the tasks before either saved keep both of their changes
+    let mut first = JsonStore::open(Path::new("pls.json")).unwrap();
+    let mut second = JsonStore::open(Path::new("pls.json")).unwrap();
+    let (first, second): (&mut dyn Store, &mut dyn Store) = (&mut first, &mut second);
+    let before = first.tasks();
+    let mut done = before.clone();
+    done[0] = done[0].make_complete();
+    let mut added = before.clone();
+    let mut new_task = Task::new(&"another task".into());
+    new_task.id = crate::next_id(second);
+    added.push(new_task);
+    assert_ne!(crate::next_id(first), added[1].id);
+    save_tasks(first, "do", &before, &done);
+    save_tasks(second, "add", &before, &added);
+    let tasks = JsonStore::open(Path::new("pls.json")).unwrap().tasks();
+    assert_eq!(tasks, vec![done[0].clone(), added[1].clone()]);
+}
+
+#[test]
+fn test_todotxt() {
+    use crate::{priority::Priority, todotxt};
+    use time::UtcOffset;
+
+    let offset = UtcOffset::UTC;
+    let text = "\
+(A) 2024-05-01 call mum +family @phone @home due:2024-05-17
+x 2024-05-03 2024-05-01 file taxes +money pri:B
+buy milk t:2024-05-10 rec:1w +errands +shopping
+(D) read https://example.com/article
+(F) standup at 10:30 re:budget with sam
+x 2024-05-04 water plants pri:E
+";
+    let tasks = todotxt::import(text, offset);
+    assert_eq!(tasks.len(), 6);
+
+    assert_eq!(tasks[0].title, "call mum");
+    assert_eq!(tasks[0].priority, Some(Priority::High));
+    assert_eq!(tasks[0].project.as_deref(), Some("family"));
+    assert_eq!(tasks[0].tags, ["phone", "home"]);
+    assert!(tasks[0].due.is_some());
+    assert!(tasks[0].created_at.is_some());
+    assert!(!tasks[0].completed);
+
+    assert!(tasks[1].completed);
+    assert_eq!(tasks[1].priority, Some(Priority::Medium));
+    assert!(tasks[1].completed_at > tasks[1].created_at);
+
+    // what pls doesn't understand is kept
+    assert_eq!(tasks[2].title, "buy milk");
+    assert_eq!(tasks[2].project.as_deref(), Some("errands"));
+    assert_eq!(tasks[2].extra, ["t:2024-05-10", "rec:1w", "+shopping"]);
+
+    assert_eq!(tasks[3].title, "read https://example.com/article");
+    assert_eq!(tasks[3].priority, Some(Priority::Low));
+
+    // times and key/values in the middle of the title stay in it
+    assert_eq!(tasks[4].title, "standup at 10:30 re:budget with sam");
+    assert_eq!(tasks[4].priority, Some(Priority::Low));
+
+    // exporting gives back the same lines, with key/values moved to the end
+    let exported = todotxt::export(&tasks, offset);
+    assert_eq!(
+        exported,
+        "\
+(A) 2024-05-01 call mum +family @phone @home due:2024-05-17
+x 2024-05-03 2024-05-01 file taxes +money pri:B
+buy milk +errands t:2024-05-10 rec:1w +shopping
+(D) read https://example.com/article
+(F) standup at 10:30 re:budget with sam
+x 2024-05-04 water plants pri:E
+"
+    );
+    assert_eq!(todotxt::import(&exported, offset), tasks);
+
+    // tasks created in pls export too
+    let mut task = Task::new(&"water plants".into());
+    task.tags = vec!["home".into()];
+    let line = todotxt::to_line(&task.make_complete(), offset);
+    assert!(line.starts_with("x 20"));
+    assert!(line.ends_with("water plants @home"));
+}
+
+#[test]
+fn test_formats() {
+    use crate::formats::{add_imported, export, import, Format, ImportCounts, JSON_VERSION};
+    use crate::priority::Priority;
+    use time::macros::datetime;
+
+    let now = datetime!(2024-05-17 12:00 UTC);
+    let mut parent = Task::new(&"plan trip".into());
+    parent.id = 1;
+    parent.priority = Some(Priority::High);
+    parent.project = Some("holiday".into());
+    parent.due = Some(datetime!(2024-05-20 9:30 UTC).unix_timestamp());
+    let mut child = Task::new(&"book flights, \"cheap\"".into()).make_complete();
+    child.id = 2;
+    child.parent = Some(1);
+    child.tags = vec!["travel".into(), "online".into()];
+    let tasks = vec![parent, child];
+
+    let markdown = export(&tasks, Format::Markdown, now).unwrap();
+    assert_eq!(
+        markdown,
+        "- [ ] plan trip @holiday (high priority, due 2024-05-20 09:30)\n  \
+         - [x] book flights, \"cheap\" +travel +online\n"
+    );
+    assert!(import(&markdown, Format::Markdown, now).is_err());
+
+    // json keeps every field, and says which version of the export it is
+    let json = export(&tasks, Format::Json, now).unwrap();
+    assert!(json.contains(&format!("\"version\": {JSON_VERSION}")));
+    assert_eq!(import(&json, Format::Json, now).unwrap().tasks, tasks);
+    let newer = json.replace(
+        &format!("\"version\": {JSON_VERSION}"),
+        &format!("\"version\": {}", JSON_VERSION + 1),
+    );
+    assert!(import(&newer, Format::Json, now).is_err());
+
+    // csv times are minutes, and quoting survives
+    let csv = export(&tasks, Format::Csv, now).unwrap();
+    assert!(csv.starts_with("id,title,completed,priority,due,"));
+    let from_csv = import(&csv, Format::Csv, now).unwrap().tasks;
+    assert_eq!(from_csv[0].due, tasks[0].due);
+    assert_eq!(from_csv[1].title, tasks[1].title);
+    assert_eq!(from_csv[1].tags, tasks[1].tags);
+    assert_eq!(from_csv[1].parent, Some(1));
+    // only the title is required
+    let minimal = import("title,priority\nwater plants,low\n", Format::Csv, now)
+        .unwrap()
+        .tasks;
+    assert_eq!(minimal[0].priority, Some(Priority::Low));
+    assert!(import("title,priority\nwater plants,urgent\n", Format::Csv, now).is_err());
+
+    // importing into a list skips duplicates and links subtasks to their new ids
+    let mut list = vec![tasks[0].clone()];
+    let mut id = 10;
+    let counts = add_imported(&mut list, from_csv.clone(), || {
+        id += 1;
+        id
+    });
+    assert_eq!(
+        counts,
+        ImportCounts {
+            added: 1,
+            updated: 0,
+            skipped: 1
+        }
+    );
+    assert_eq!(list[1].id, 11);
+    assert_eq!(list[1].parent, Some(1));
+    let counts = add_imported(&mut list, from_csv, || unreachable!());
+    assert_eq!((counts.added, counts.skipped), (0, 2));
+}
+
+#[test]
+fn test_ical() {
+    use crate::formats::{add_imported, ImportCounts};
+    use crate::{ical, priority::Priority, recur::Recurrence};
+    use time::{macros::datetime, UtcOffset};
+
+    let now = datetime!(2024-05-17 12:00 UTC);
+    // a calendar from another app, with a folded line, a date-only due date and a subtask
+    let calendar = "BEGIN:VCALENDAR\r\n\
+VERSION:2.0\r\n\
+PRODID:-//Example Corp//Tasks//EN\r\n\
+BEGIN:VTODO\r\n\
+UID:20240501-trip@example.com\r\n\
+SUMMARY:Plan the summer trip\\, with a very long description that goes on and\r\n  \
+ on\r\n\
+DUE;VALUE=DATE:20240520\r\n\
+PRIORITY:2\r\n\
+CATEGORIES:Travel,Family Stuff\r\n\
+STATUS:NEEDS-ACTION\r\n\
+RRULE:FREQ=WEEKLY;BYDAY=FR\r\n\
+END:VTODO\r\n\
+BEGIN:VTODO\r\n\
+UID:20240501-flights@example.com\r\n\
+SUMMARY:Book flights\r\n\
+DUE;TZID=Europe/Paris:20240518T093000\r\n\
+STATUS:COMPLETED\r\n\
+COMPLETED:20240502T080000Z\r\n\
+RELATED-TO:20240501-trip@example.com\r\n\
+END:VTODO\r\n\
+END:VCALENDAR\r\n";
+    let tasks = ical::import(calendar, UtcOffset::UTC).unwrap();
+    assert_eq!(tasks.len(), 2);
+    assert_eq!(
+        tasks[0].title,
+        "Plan the summer trip, with a very long description that goes on and on"
+    );
+    assert_eq!(
+        tasks[0].due,
+        Some(datetime!(2024-05-20 23:59 UTC).unix_timestamp())
+    );
+    assert_eq!(tasks[0].priority, Some(Priority::High));
+    assert_eq!(tasks[0].tags, ["travel", "family-stuff"]);
+    assert_eq!(
+        tasks[0].recurrence,
+        Some(Recurrence::Weekly(time::Weekday::Friday))
+    );
+    assert!(tasks[1].completed);
+    assert_eq!(
+        tasks[1].completed_at,
+        Some(datetime!(2024-05-02 8:00 UTC).unix_timestamp())
+    );
+    assert_eq!(tasks[1].parent, Some(tasks[0].id));
+
+    // importing gives the tasks ids in the list, and importing again updates them
+    let mut list = vec![];
+    let mut id = 0;
+    let mut next_id = || {
+        id += 1;
+        id
+    };
+    assert_eq!(add_imported(&mut list, tasks, &mut next_id).added, 2);
+    assert_eq!(list[1].parent, Some(list[0].id));
+    let renamed = calendar.replace("SUMMARY:Book flights", "SUMMARY:Book trains");
+    let counts = add_imported(
+        &mut list,
+        ical::import(&renamed, UtcOffset::UTC).unwrap(),
+        &mut next_id,
+    );
+    assert_eq!(
+        counts,
+        ImportCounts {
+            added: 0,
+            updated: 1,
+            skipped: 0
+        }
+    );
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[1].title, "Book trains");
+    assert_eq!(list[1].parent, Some(list[0].id));
+
+    // exported lines are folded, and tasks come back the same with their UIDs
+    let exported = ical::export(&list, now);
+    assert!(exported.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(exported.lines().all(|line| line.len() <= 75));
+    assert!(exported.contains("UID:20240501-trip@example.com\r\n"));
+    assert!(exported.contains("STATUS:COMPLETED\r\n"));
+    assert!(exported.contains("CATEGORIES:travel,family-stuff\r\n"));
+    assert!(exported.contains("RRULE:FREQ=WEEKLY;BYDAY=FR\r\n"));
+    let counts = add_imported(
+        &mut list,
+        ical::import(&exported, UtcOffset::UTC).unwrap(),
+        &mut next_id,
+    );
+    assert_eq!(counts, ImportCounts::default());
+
+    // tasks created in pls get a UID of their own, which finds them again
+    let mut task = Task::new(&"water plants".into());
+    task.id = 7;
+    let exported = ical::export(&[task.clone()], now);
+    let mut list = vec![task];
+    let mut reimported = ical::import(&exported, UtcOffset::UTC).unwrap();
+    reimported[0].title = "water the plants".into();
+    let counts = add_imported(&mut list, reimported, || unreachable!());
+    assert_eq!(counts.updated, 1);
+    assert_eq!(list[0].title, "water the plants");
+
+    // what a calendar from another app leaves out is kept
+    let mut task = Task::new(&"water plants".into());
+    task.id = 7;
+    task.project = Some("home".into());
+    task.tags = vec!["garden".into()];
+    task.recurrence = Some(Recurrence::Daily);
+    let mut list = vec![task.clone()];
+    let mut reimported = ical::import(&ical::export(&list, now), UtcOffset::UTC).unwrap();
+    reimported[0].title = "water the plants".into();
+    reimported[0].project = None;
+    reimported[0].tags.clear();
+    reimported[0].recurrence = None;
+    reimported[0].created_at = None;
+    add_imported(&mut list, reimported, || unreachable!());
+    assert_eq!(list[0].title, "water the plants");
+    assert_eq!(
+        (&list[0].project, &list[0].tags, list[0].recurrence),
+        (&task.project, &task.tags, task.recurrence)
+    );
+    assert_eq!(list[0].created_at, task.created_at);
+}
+
+#[test]
+fn test_taskwarrior() {
+    use crate::formats::{add_imported, ImportCounts};
+    use crate::{priority::Priority, recur::Recurrence, taskwarrior};
+    use time::{macros::datetime, UtcOffset, Weekday};
+
+    let now = datetime!(2024-05-17 12:00 UTC);
+    let uuid = |n: u64| format!("a1b2c3d4-0000-4000-8000-{n:012}");
+    // what `task export` writes, with a subtask, a recurring task and fields pls has no use for
+    let export = format!(
+        r#"[
+{{"id":1,"description":"Water the garden","due":"20240520T220000Z","entry":"20240501T080000Z","modified":"20240502T080000Z","project":"Home","priority":"H","status":"pending","tags":["outside"],"uuid":"{}","annotations":[{{"entry":"20240501T090000Z","description":"use the grey hose"}}],"depends":["{}"],"urgency":9.1}},
+{{"id":2,"description":"Buy a hose","entry":"20240501T080000Z","status":"waiting","uuid":"{}","wait":"20240518T000000Z","scheduled":"20240519T000000Z"}},
+{{"id":0,"description":"Old idea","entry":"20240401T080000Z","end":"20240402T080000Z","status":"deleted","uuid":"{}"}},
+{{"id":0,"description":"Take out bins","due":"20240506T200000Z","entry":"20240501T080000Z","mask":"+-","recur":"weekly","status":"recurring","uuid":"{}"}},
+{{"id":3,"description":"Take out bins","due":"20240520T200000Z","entry":"20240513T080000Z","imask":2,"parent":"{}","recur":"weekly","status":"pending","uuid":"{}"}},
+{{"id":0,"description":"Take out bins","due":"20240506T200000Z","end":"20240506T190000Z","entry":"20240501T080000Z","imask":0,"parent":"{}","recur":"weekly","status":"completed","uuid":"{}"}},
+{{"id":0,"description":"Pay taxes","due":"20240415T200000Z","entry":"20240101T080000Z","recur":"quarterly","status":"recurring","uuid":"{}"}}
+]"#,
+        uuid(1),
+        uuid(2),
+        uuid(2),
+        uuid(3),
+        uuid(4),
+        uuid(4),
+        uuid(5),
+        uuid(4),
+        uuid(6),
+        uuid(7)
+    );
+    let (tasks, unmapped) = taskwarrior::import(&export, UtcOffset::UTC).unwrap();
+    let titles = tasks.iter().map(|t| t.title.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        titles,
+        [
+            "Water the garden",
+            "Buy a hose",
+            "Take out bins",
+            "Take out bins",
+            "Pay taxes"
+        ]
+    );
+    assert_eq!(tasks[0].project.as_deref(), Some("home"));
+    assert_eq!(tasks[0].priority, Some(Priority::High));
+    assert_eq!(tasks[0].tags, ["outside"]);
+    assert_eq!(tasks[0].notes[0].text, "use the grey hose");
+    assert_eq!(tasks[0].uid, Some(uuid(1)));
+    // the tasks a task depends on become its subtasks
+    assert_eq!(tasks[1].parent, Some(tasks[0].id));
+    assert!(!tasks[1].completed);
+    // recurring tasks come in once, due when their next instance is
+    assert_eq!(
+        tasks[2].due,
+        Some(datetime!(2024-05-20 20:00 UTC).unix_timestamp())
+    );
+    assert_eq!(
+        tasks[2].recurrence,
+        Some(Recurrence::Weekly(Weekday::Monday))
+    );
+    assert!(tasks[3].completed && tasks[3].recurrence.is_none());
+    assert_eq!(tasks[4].recurrence, None);
+    assert_eq!(
+        unmapped.into_iter().collect::<Vec<_>>(),
+        [
+            ("recur".to_string(), 1),
+            ("scheduled".to_string(), 1),
+            ("status:deleted".to_string(), 1),
+            ("wait".to_string(), 1)
+        ]
+    );
+
+    // exporting writes what was imported, and importing it again changes nothing
+    let mut list = vec![];
+    let mut id = 0;
+    let mut next_id = || {
+        id += 1;
+        id
+    };
+    assert_eq!(add_imported(&mut list, tasks, &mut next_id).added, 5);
+    let exported = taskwarrior::export(&list, now).unwrap();
+    assert!(exported.contains("\"status\": \"recurring\""));
+    assert!(exported.contains("\"recur\": \"weekly\""));
+    assert!(exported.contains(&format!("\"depends\": [\n      \"{}\"", uuid(2))));
+    let (reimported, unmapped) = taskwarrior::import(&exported, UtcOffset::UTC).unwrap();
+    assert!(unmapped.is_empty());
+    assert_eq!(
+        add_imported(&mut list, reimported, &mut next_id),
+        ImportCounts::default()
+    );
+
+    // tasks created in pls get a UUID that finds them again
+    let mut task = Task::new(&"water plants".into());
+    task.id = 7;
+    let exported = taskwarrior::export(&[task.clone()], now).unwrap();
+    assert!(exported.contains(&format!("\"uuid\": \"{}\"", taskwarrior::uuid(&task))));
+    let edited = exported.replace("water plants", "water the plants");
+    let mut list = vec![task];
+    let (reimported, _) = taskwarrior::import(&edited, UtcOffset::UTC).unwrap();
+    let counts = add_imported(&mut list, reimported, || unreachable!());
+    assert_eq!(counts.updated, 1);
+    assert_eq!(list[0].title, "water the plants");
+}
+
+#[test]
+#[sealed_test]
+fn test_lists() {
+    use crate::journal::{revert, save_tasks};
+    use crate::{lists, store::Store};
+
+    let mut db = PickleDb::load_or_new(
+        "test",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to create db");
+
+    assert_eq!(lists::parse("Work"), Ok(Some("work".to_string())));
+    assert_eq!(lists::parse("default"), Ok(None));
+    assert!(lists::parse("side projects").is_err());
+    assert!(lists::parse("").is_err());
+
+    let task = |id, title: &str, list: Option<&str>| Task {
+        id,
+        title: title.into(),
+        list: list.map(str::to_string),
+        ..Task::default()
+    };
+    let home = vec![task(1, "water plants", None)];
+    let work = vec![task(2, "send report", Some("work"))];
+    save_tasks(&mut db, "add water plants", &[], &home);
+    save_tasks(&mut db, "--list work add send report", &[], &work);
+    assert_eq!(get_tasks(&db).len(), 2);
+    assert_eq!(lists::tasks_in(&get_tasks(&db), None), home);
+    assert_eq!(lists::tasks_in(&get_tasks(&db), Some("work")), work);
+    assert_eq!(
+        lists::all(&get_tasks(&db)),
+        [None, Some("work".to_string())]
+    );
+
+    // changing one list leaves the others alone
+    save_tasks(&mut db, "--list work rm --all", &work, &[]);
+    assert_eq!(get_tasks(&db), home);
+    assert_eq!(lists::all(&get_tasks(&db)), [None]);
+
+    // moving a task saves it in its new list
+    let moved = vec![task(1, "water plants", Some("work"))];
+    save_tasks(&mut db, "move 1 work", &home, &moved);
+    assert!(lists::tasks_in(&get_tasks(&db), None).is_empty());
+    assert_eq!(lists::tasks_in(&get_tasks(&db), Some("work")), moved);
+
+    // reverting restores every list
+    assert_eq!(revert(&mut db, 2).len(), 2);
+    let mut all = get_tasks(&db);
+    all.sort_by_key(|t| t.id);
+    assert_eq!(all, [home, work].concat());
+
+    // and the tasks stay in the order they are stored when one list changes
+    db.set_tasks(&all).unwrap();
+    let home = lists::tasks_in(&all, None);
+    let mut done = home.clone();
+    done[0].completed = true;
+    save_tasks(&mut db, "do 1", &home, &done);
+    let ids = get_tasks(&db).iter().map(|t| t.id).collect::<Vec<_>>();
+    assert_eq!(ids, [1, 2]);
+    assert!(get_tasks(&db)[0].completed);
+}
+
+#[test]
+#[sealed_test]
+fn test_project_store() {
+    use crate::store::{find_project, ProjectStore, Store};
+    use std::{fs, path::Path};
+
+    fs::create_dir_all("project/src/deep").unwrap();
+    assert_eq!(find_project(Path::new("project/src/deep")), None);
+    // found from any directory below it, the nearest one first
+    fs::write("project/.pls.json", "{}").unwrap();
+    assert_eq!(
+        find_project(Path::new("project/src/deep")).as_deref(),
+        Some(Path::new("project/.pls.json"))
+    );
+    fs::create_dir("project/src/.pls").unwrap();
+    assert_eq!(
+        find_project(Path::new("project/src/deep")).as_deref(),
+        Some(Path::new("project/src/.pls/tasks.json"))
+    );
+
+    // the project file only holds the tasks, readable by anyone, and the journal stays private
+    let path = Path::new("project/.pls.json");
+    let mut store = ProjectStore::open(path, Path::new("project/config"), None).unwrap();
+    let tasks = vec![Task::new(&"write docs".into())];
+    crate::journal::save_tasks(&mut store, "add --local write docs", &[], &tasks);
+    let shared = fs::read_to_string(path).unwrap();
+    assert!(shared.contains("\n  \"tasks\": ["));
+    assert!(shared.contains("\"title\": \"write docs\""));
+    assert!(!shared.contains("journal"));
+    let reopened = ProjectStore::open(path, Path::new("project/config"), None).unwrap();
+    assert_eq!(reopened.tasks(), tasks);
+    assert_eq!(crate::journal::revert(&mut store, 1).len(), 1);
+    assert!(store.tasks().is_empty());
+}
+
+#[test]
+#[sealed_test]
+fn test_scan() {
+    use crate::scan::{find_comments, import, scan, update_origins};
+    use std::{fs, path::Path};
+
+    assert_eq!(
+        find_comments(
+            "// TODO(ann): fix this\nlet todo = \"TODO\";\n    # FIXME - later\n/* HACK */\n// TODOS"
+        ),
+        vec![
+            (1, "TODO".to_string(), "fix this".to_string()),
+            (3, "FIXME".to_string(), "later".to_string()),
+            (4, "HACK".to_string(), "HACK".to_string()),
+        ]
+    );
+
+    // files in .gitignore are skipped, even outside of a git repository
+    fs::create_dir_all("scanned/target").unwrap();
+    fs::write("scanned/.gitignore", "target/\n").unwrap();
+    fs::write("scanned/main.rs", "fn main() {}\n// TODO: add a test\n").unwrap();
+    fs::write("scanned/target/out.rs", "// TODO: generated\n").unwrap();
+    let root = Path::new("scanned");
+    let comments = scan(root).unwrap();
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].location(), "scanned/main.rs:2");
+
+    let mut tasks = Vec::new();
+    let mut id = 0;
+    assert_eq!(
+        import(&mut tasks, &comments, || {
+            id += 1;
+            id
+        }),
+        1
+    );
+    assert_eq!(tasks[0].title, "add a test");
+    assert_eq!(tasks[0].tags, vec!["todo"]);
+    // comments that are tasks already aren't imported again
+    assert_eq!(import(&mut tasks, &comments, || 2), 0);
+
+    // the task follows its comment when lines are added above it, wherever the scan is run from
+    fs::write("scanned/main.rs", "\n\n// TODO: add a test\n").unwrap();
+    let other_root = Path::new("scanned/../scanned");
+    let comments = scan(other_root).unwrap();
+    assert!(update_origins(&mut tasks, &comments, other_root).is_empty());
+    assert_eq!(tasks[0].origin.as_ref().unwrap().line, 3);
+
+    // and is resolved once it is removed, only once
+    fs::write("scanned/main.rs", "fn main() {}\n").unwrap();
+    let comments = scan(root).unwrap();
+    assert_eq!(update_origins(&mut tasks, &comments, root), vec![0]);
+    assert!(tasks[0].origin.as_ref().unwrap().resolved);
+    assert!(update_origins(&mut tasks, &comments, root).is_empty());
+    // scans of another directory leave it alone
+    fs::write("scanned/main.rs", "// TODO: add a test\n").unwrap();
+    fs::create_dir_all("elsewhere").unwrap();
+    tasks[0].origin.as_mut().unwrap().resolved = false;
+    assert!(update_origins(&mut tasks, &[], Path::new("elsewhere")).is_empty());
+    assert!(!tasks[0].origin.as_ref().unwrap().resolved);
+    assert!(update_origins(&mut tasks, &[], Path::new("missing")).is_empty());
+}
+
+#[test]
+fn test_checklist() {
+    use crate::{
+        checklist::{self, Checklist, Style},
+        priority::Priority,
+    };
+
+    let text = "# Notes\n\nSome prose.\n\n- [ ] buy milk +home\n- [x] write report\n  * [X] outline !!\n\n```\n- [ ] not a task\n```\n";
+    let mut checklist = Checklist::parse(text, Style::Markdown);
+    let mut tasks = checklist.tasks();
+    assert_eq!(tasks.len(), 3);
+    assert_eq!(tasks[0].title, "buy milk");
+    assert_eq!(tasks[0].tags, vec!["home"]);
+    assert!(tasks[1].completed);
+    assert_eq!(tasks[2].parent, Some(2));
+    assert_eq!(tasks[2].priority, Some(Priority::Medium));
+
+    // unchanged tasks keep their line, and everything that isn't a task stays
+    tasks[0].completed = true;
+    tasks[0].updated_at = Some(1);
+    tasks.remove(2);
+    tasks.push(Task {
+        id: 4,
+        parent: Some(2),
+        ..Task::new(&"draft".to_string())
+    });
+    tasks.push(Task {
+        id: 5,
+        project: Some("family".to_string()),
+        ..Task::new(&"call mum".to_string())
+    });
+    checklist.update(&tasks);
+    assert_eq!(
+        checklist.text(),
+        "# Notes\n\nSome prose.\n\n- [x] buy milk +home\n- [x] write report\n  - [ ] draft\n- [ ] call mum @family\n\n```\n- [ ] not a task\n```\n"
+    );
+    assert_eq!(checklist.tasks().len(), 4);
+
+    // org headlines, with the text below a headline belonging to it
+    let text = "#+TITLE: todo\n* Work\n** TODO [#A] fix the build :ci:\n   it fails on windows\n** DONE release\n* TODO call mum\n";
+    let mut checklist = Checklist::parse(text, Style::Org);
+    let mut tasks = checklist.tasks();
+    assert_eq!(tasks.len(), 3);
+    assert_eq!(tasks[0].title, "fix the build");
+    assert_eq!(tasks[0].priority, Some(Priority::High));
+    assert_eq!(tasks[0].tags, vec!["ci"]);
+    // a headline that isn't a task ends the tasks above it
+    assert_eq!(tasks[2].parent, None);
+    tasks.remove(0);
+    tasks[1].completed = true;
+    tasks.push(Task {
+        id: 4,
+        parent: Some(3),
+        tags: vec!["family".to_string()],
+        ..Task::new(&"buy flowers".to_string())
+    });
+    checklist.update(&tasks);
+    assert_eq!(
+        checklist.text(),
+        "#+TITLE: todo\n* Work\n** DONE release\n* DONE call mum\n** TODO buy flowers :family:\n"
+    );
+
+    // the subtasks of a removed task don't end up below the task above it
+    let text = "- [ ] pack\n- [ ] trip\n  - [ ] book\n    - [ ] compare prices\n  - [ ] visa\n";
+    let mut checklist = Checklist::parse(text, Style::Markdown);
+    let mut tasks = checklist.tasks();
+    tasks.remove(1);
+    checklist.update(&tasks);
+    assert_eq!(
+        checklist.text(),
+        "- [ ] pack\n- [ ] book\n  - [ ] compare prices\n- [ ] visa\n"
+    );
+    assert!(checklist.tasks().iter().all(|t| t.parent != Some(1)));
+
+    // fields a checklist has no place for are reported rather than dropped
+    let mut task = Task::new(&"pay rent".to_string());
+    assert!(checklist::unsupported(&task).is_empty());
+    task.due = Some(0);
+    task.recurrence = Some(crate::recur::Recurrence::Daily);
+    assert_eq!(checklist::unsupported(&task), ["due date", "recurrence"]);
+}
+
+#[test]
+#[sealed_test]
+fn test_sync() {
+    use crate::{
+        store::{JsonStore, Store},
+        sync,
+    };
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path, process::Command};
+
+    let status = Command::new("git")
+        .args(["init", "--quiet", "--bare", "remote.git"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let (laptop_dir, desktop_dir) = (Path::new("laptop"), Path::new("desktop"));
+    fs::create_dir(laptop_dir).unwrap();
+    fs::create_dir(desktop_dir).unwrap();
+    let mut laptop: Box<dyn Store> =
+        Box::new(JsonStore::open(&laptop_dir.join("pls.json")).unwrap());
+    let mut desktop: Box<dyn Store> =
+        Box::new(JsonStore::open(&desktop_dir.join("pls.json")).unwrap());
+    laptop
+        .set_tasks(&[new_task(1, "buy milk"), new_task(2, "write report")])
+        .unwrap();
+    desktop.set_tasks(&[new_task(1, "call mum")]).unwrap();
+
+    // tasks added on both machines before they ever synced are all kept, the ones not pushed
+    // yet getting a new id if theirs is taken
+    sync::init(laptop_dir, "remote.git").unwrap();
+    sync::sync(laptop.as_mut(), laptop_dir, "sync").unwrap();
+    sync::init(desktop_dir, "remote.git").unwrap();
+    let synced = sync::sync(desktop.as_mut(), desktop_dir, "sync").unwrap();
+    assert_eq!(synced.before.len(), 1);
+    assert_eq!(
+        titles(&desktop.tasks()),
+        vec![
+            (1, "buy milk", false, None),
+            (2, "write report", false, None),
+            (3, "call mum", false, None),
+        ]
+    );
+    sync::sync(laptop.as_mut(), laptop_dir, "sync").unwrap();
+    assert_eq!(titles(&laptop.tasks()), titles(&desktop.tasks()));
+
+    // changes to different tasks are all kept, and the last change to the same task wins
+    let mut tasks = laptop.tasks();
+    tasks[0].title = "call dad".to_string();
+    tasks[0].updated_at = Some(10);
+    tasks[1].completed = true;
+    tasks.push(new_task(4, "new on laptop"));
+    laptop.set_tasks(&tasks).unwrap();
+    let mut tasks = desktop.tasks();
+    tasks[0].title = "call mum and dad".to_string();
+    tasks[0].updated_at = Some(20);
+    tasks.remove(2);
+    tasks.push(new_task(4, "new on desktop"));
+    desktop.set_tasks(&tasks).unwrap();
+    sync::sync(desktop.as_mut(), desktop_dir, "sync").unwrap();
+    sync::sync(laptop.as_mut(), laptop_dir, "sync").unwrap();
+    sync::sync(desktop.as_mut(), desktop_dir, "sync").unwrap();
+    let expected = vec![
+        (1, "call mum and dad", false, None),
+        (2, "write report", true, None),
+        (4, "new on desktop", false, None),
+        (5, "new on laptop", false, None),
+    ];
+    assert_eq!(titles(&laptop.tasks()), expected);
+    assert_eq!(titles(&desktop.tasks()), expected);
+    // new ids don't clash with the ones given on the other machine
+    assert_eq!(laptop.get::<u64>("task-id"), Some(6));
+    // and the merge can be reverted like any other change
+    assert_eq!(crate::journal::revert(laptop.as_mut(), 1).len(), 1);
+    assert_eq!(laptop.tasks()[0].title, "call dad");
+
+    // when the remote refuses the push, the tasks merged from it are still saved here
+    let mut tasks = desktop.tasks();
+    tasks.push(new_task(6, "new on desktop again"));
+    desktop.set_tasks(&tasks).unwrap();
+    sync::sync(desktop.as_mut(), desktop_dir, "sync").unwrap();
+    let hook = Path::new("remote.git/hooks/pre-receive");
+    fs::write(hook, "#!/bin/sh\nexit 1\n").unwrap();
+    fs::set_permissions(hook, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut tasks = laptop.tasks();
+    tasks.iter_mut().find(|t| t.id == 4).unwrap().completed = true;
+    laptop.set_tasks(&tasks).unwrap();
+    assert!(sync::sync(laptop.as_mut(), laptop_dir, "sync").is_err());
+    let tasks = laptop.tasks();
+    let mut merged = titles(&tasks);
+    assert!(merged.contains(&(4, "new on laptop", true, None)));
+    assert!(merged.contains(&(6, "new on desktop again", false, None)));
+    // and pushed by the next sync, without losing the changes of either machine
+    fs::remove_file(hook).unwrap();
+    sync::sync(laptop.as_mut(), laptop_dir, "sync").unwrap();
+    sync::sync(desktop.as_mut(), desktop_dir, "sync").unwrap();
+    merged.sort();
+    for db in [laptop.as_ref(), desktop.as_ref()] {
+        let tasks = db.tasks();
+        let mut synced = titles(&tasks);
+        synced.sort_unstable();
+        assert_eq!(synced, merged);
+    }
+
+    // tasks added on each machine with the same id are both kept, once, with the same ids on both
+    for (db, created_at) in [(laptop.as_mut(), 100), (desktop.as_mut(), 200)] {
+        let mut tasks = db.tasks();
+        tasks.push(Task {
+            created_at: Some(created_at),
+            ..new_task(7, "water plants")
+        });
+        db.set_tasks(&tasks).unwrap();
+    }
+    for _ in 0..3 {
+        sync::sync(laptop.as_mut(), laptop_dir, "sync").unwrap();
+        sync::sync(desktop.as_mut(), desktop_dir, "sync").unwrap();
+    }
+    let added = |db: &dyn Store| {
+        let mut added = db
+            .tasks()
+            .into_iter()
+            .filter(|t| t.title == "water plants")
+            .map(|t| (t.id, t.created_at))
+            .collect::<Vec<_>>();
+        added.sort_unstable();
+        added
+    };
+    assert_eq!(added(laptop.as_ref()), vec![(7, Some(100)), (8, Some(200))]);
+    assert_eq!(added(desktop.as_ref()), added(laptop.as_ref()));
+}
+
+#[test]
+#[sealed_test]
+fn test_merge() {
+    use crate::{
+        journal::save_tasks,
+        merge::{self, merge, Side, Strategy, Tombstone},
+    };
+
+    let task = |id, title, updated_at| Task {
+        updated_at: Some(updated_at),
+        ..new_task(id, title)
+    };
+
+    // removed tasks leave a tombstone, so that they don't come back from a copy that has them
+    let mut db = PickleDb::load_or_new(
+        "test",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to create db");
+    let ours = vec![task(1, "one", 5), task(2, "two", 1), task(3, "three", 1)];
+    save_tasks(&mut db, "add", &[], &ours);
+    save_tasks(&mut db, "rm 3", &ours, &ours[..2]);
+    let tombstones = merge::tombstones(&db);
+    assert_eq!(tombstones.len(), 1);
+    assert_eq!(tombstones[0].id, 3);
+
+    // without a base, the task changed last is kept
+    let theirs = vec![
+        task(1, "one", 9),
+        task(2, "two", 1),
+        task(3, "three", 1),
+        task(4, "four", 1),
+    ];
+    let merged = merge(
+        &merge::base(&db),
+        &merge::renumbered(&db),
+        &Side {
+            tasks: &get_tasks(&db),
+            tombstones: &tombstones,
+        },
+        &Side {
+            tasks: &theirs,
+            tombstones: &[],
+        },
+        Strategy::Newest,
+    );
+    assert_eq!(
+        titles(&merged.tasks),
+        vec![
+            (1, "one", false, None),
+            (2, "two", false, None),
+            (4, "four", false, None)
+        ]
+    );
+    assert_eq!(merged.tasks[0].updated_at, Some(9));
+    assert_eq!((merged.added.len(), merged.updated.len()), (1, 1));
+    assert!(merged.conflicts.is_empty());
+    assert_eq!(merged.tombstones, tombstones);
+    merge::save(&mut db, &merged, &theirs, &[]).unwrap();
+    assert_eq!(merge::base(&db).len(), 3);
+    assert_eq!(db.get::<u64>("task-id"), Some(5));
+
+    // a task changed on both sides since the base is a conflict, kept as the strategy says
+    let base = vec![task(1, "one", 1)];
+    let ours = vec![task(1, "ours", 5)];
+    let theirs = vec![task(1, "theirs", 3)];
+    for (strategy, kept) in [
+        (Strategy::Ours, "ours"),
+        (Strategy::Theirs, "theirs"),
+        (Strategy::Newest, "ours"),
+    ] {
+        let merged = merge(
+            &base,
+            &[],
+            &Side {
+                tasks: &ours,
+                tombstones: &[],
+            },
+            &Side {
+                tasks: &theirs,
+                tombstones: &[],
+            },
+            strategy,
+        );
+        assert_eq!(merged.tasks[0].title, kept);
+        assert_eq!(merged.conflicts.len(), 1);
+    }
+
+    // and so is a task changed after it was removed on the other side
+    let removed = [Tombstone::new(&task(1, "one", 1), 4)];
+    let merged = merge(
+        &base,
+        &[],
+        &Side {
+            tasks: &ours,
+            tombstones: &[],
+        },
+        &Side {
+            tasks: &[],
+            tombstones: &removed,
+        },
+        Strategy::Theirs,
+    );
+    assert!(merged.tasks.is_empty());
+    assert_eq!(merged.conflicts[0].reason, "changed here, removed there");
+
+    // tasks added on both sides with the same id are both kept
+    let merged = merge(
+        &[],
+        &[],
+        &Side {
+            tasks: &[task(1, "ours", 1)],
+            tombstones: &[],
+        },
+        &Side {
+            tasks: &[
+                task(1, "theirs", 1),
+                Task {
+                    parent: Some(1),
+                    ..task(2, "sub", 1)
+                },
+            ],
+            tombstones: &[],
+        },
+        Strategy::Newest,
+    );
+    assert_eq!(
+        titles(&merged.tasks),
+        vec![
+            (1, "ours", false, None),
+            (2, "sub", false, Some(3)),
+            (3, "theirs", false, None)
+        ]
+    );
+
+    // and merging them again takes theirs for the task it was renumbered to, not a new one
+    let mut db = PickleDb::load_or_new(
+        "clash",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to create db");
+    save_tasks(&mut db, "add", &[], &[task(1, "ours", 1)]);
+    let merge_from = |db: &mut PickleDb, theirs: &[Task]| {
+        let ours = get_tasks(db);
+        let renumbered = merge::renumbered(db);
+        let merged = merge(
+            &merge::base(db),
+            &renumbered,
+            &Side {
+                tasks: &ours,
+                tombstones: &[],
+            },
+            &Side {
+                tasks: theirs,
+                tombstones: &[],
+            },
+            Strategy::Newest,
+        );
+        save_tasks(db, "merge", &ours, &merged.tasks);
+        merge::save(db, &merged, theirs, &renumbered).unwrap();
+    };
+    let mut theirs = vec![
+        task(1, "theirs", 1),
+        Task {
+            parent: Some(1),
+            ..task(2, "sub", 1)
+        },
+    ];
+    merge_from(&mut db, &theirs);
+    merge_from(&mut db, &theirs);
+    let expected = vec![
+        (1, "ours", false, None),
+        (2, "sub", false, Some(3)),
+        (3, "theirs", false, None),
+    ];
+    assert_eq!(titles(&get_tasks(&db)), expected);
+    // their later changes reach it, and a task they add with its id is another clash
+    theirs[0].title = "theirs changed".to_string();
+    theirs[0].updated_at = Some(2);
+    theirs.push(task(3, "three", 1));
+    merge_from(&mut db, &theirs);
+    merge_from(&mut db, &theirs);
+    let merged = vec![
+        (1, "ours", false, None),
+        (2, "sub", false, Some(3)),
+        (3, "theirs changed", false, None),
+        (4, "three", false, None),
+    ];
+    assert_eq!(titles(&get_tasks(&db)), merged);
+
+    // merging back into their copy takes their tasks renumbered here for theirs, and merging
+    // again either way changes nothing, each side keeping its ids
+    let mut other = PickleDb::load_or_new(
+        "other",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to create db");
+    save_tasks(&mut other, "add", &[], &theirs);
+    for _ in 0..2 {
+        crate::merge_tasks(&mut other, &db, Strategy::Newest, "merge");
+        crate::merge_tasks(&mut db, &other, Strategy::Newest, "merge");
+    }
+    assert_eq!(
+        titles(&get_tasks(&other)),
+        vec![
+            (1, "theirs changed", false, None),
+            (2, "sub", false, Some(1)),
+            (3, "three", false, None),
+            (4, "ours", false, None),
+        ]
+    );
+    assert_eq!(titles(&get_tasks(&db)), merged);
+}
+
+// a task as the tests of merging and syncing add it, created at its id
+fn new_task(id: u64, title: &str) -> Task {
+    Task {
+        id,
+        title: title.to_string(),
+        created_at: Some(id.try_into().unwrap()),
+        updated_at: Some(1),
+        ..Task::default()
+    }
+}
+
+// the id, title, completion and parent of each task, to compare with the ones expected
+fn titles(tasks: &[Task]) -> Vec<(u64, &str, bool, Option<u64>)> {
+    tasks
+        .iter()
+        .map(|t| (t.id, t.title.as_str(), t.completed, t.parent))
+        .collect()
+}
+
+// the resources of a mock CalDAV server by path, as an ETag and a calendar
+type Collection =
+    std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<String, (u64, String)>>>;
+
+// a CalDAV server with one collection at /tasks/, that lets "ann" in with the password "secret".
+// some servers don't give the ETag of an uploaded task, which `put_etags` leaves out too
+fn caldav_server(put_etags: bool) -> (String, Collection) {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/tasks/", listener.local_addr().unwrap());
+    let resources: Collection = Arc::new(Mutex::new(std::collections::BTreeMap::new()));
+    let server = Arc::clone(&resources);
+    std::thread::spawn(move || {
+        let mut next_etag = 1;
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut parts = request.split_whitespace();
+            let (method, path) = (
+                parts.next().unwrap_or_default(),
+                parts.next().unwrap_or_default(),
+            );
+            let mut headers = std::collections::HashMap::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let Some((name, value)) = line.trim_end().split_once(':') else {
+                    break;
+                };
+                headers.insert(name.to_lowercase(), value.trim().to_string());
+            }
+            let length = headers
+                .get("content-length")
+                .map_or(0, |l| l.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let body = String::from_utf8(body).unwrap();
+
+            let mut resources = server.lock().unwrap();
+            let current = resources.get(path).map(|(etag, _)| format!("\"{etag}\""));
+            let if_match = headers.get("if-match");
+            let (status, etag, response) = if headers.get("authorization").map(String::as_str)
+                != Some("Basic YW5uOnNlY3JldA==")
+            {
+                ("401 Unauthorized", None, String::new())
+            } else if if_match.is_some_and(|etag| Some(etag) != current.as_ref())
+                || headers.contains_key("if-none-match") && current.is_some()
+            {
+                ("412 Precondition Failed", None, String::new())
+            } else {
+                match method {
+                    "REPORT" => {
+                        // the collection itself is listed too, without calendar data
+                        let responses = resources
+                            .iter()
+                            .map(|(href, (etag, calendar))| {
+                                let calendar = calendar
+                                    .replace('&', "&amp;")
+                                    .replace('<', "&lt;")
+                                    .replace('\r', "&#13;");
+                                format!("<d:response><d:href>{href}</d:href><d:propstat><d:prop><d:getetag>&quot;{etag}&quot;</d:getetag><cal:calendar-data>{calendar}</cal:calendar-data></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>\n")
+                            })
+                            .collect::<Vec<_>>()
+                            .concat();
+                        let xml = format!("<?xml version=\"1.0\"?>\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\">\n<d:response><d:href>/tasks/</d:href><d:propstat><d:prop><d:getetag/></d:prop></d:propstat></d:response>\n{responses}</d:multistatus>\n");
+                        ("207 Multi-Status", None, xml)
+                    }
+                    "PUT" => {
+                        resources.insert(path.to_string(), (next_etag, body));
+                        next_etag += 1;
+                        (
+                            "201 Created",
+                            Some(next_etag - 1).filter(|_| put_etags),
+                            String::new(),
+                        )
+                    }
+                    "GET" => match resources.get(path) {
+                        Some((etag, calendar)) => ("200 OK", Some(*etag), calendar.clone()),
+                        None => ("404 Not Found", None, String::new()),
+                    },
+                    "DELETE" => {
+                        resources.remove(path);
+                        ("204 No Content", None, String::new())
+                    }
+                    _ => ("405 Method Not Allowed", None, String::new()),
+                }
+            };
+            drop(resources);
+            let etag = etag.map_or_else(String::new, |etag| format!("ETag: \"{etag}\"\r\n"));
+            write!(
+                stream,
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n{etag}\r\n{response}",
+                response.len()
+            )
+            .unwrap();
+        }
+    });
+    (url, resources)
+}
+
+#[test]
+#[sealed_test]
+fn test_caldav() {
+    use crate::{caldav, config, journal::save_tasks, store::Store};
+
+    let (url, server) = caldav_server(true);
+    let mut db = PickleDb::load_or_new(
+        "test",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to create db");
+    // change a task on the server, as another client would
+    let edit = |uid: &str, from: &str, to: &str| {
+        let mut resources = server.lock().unwrap();
+        let (etag, calendar) = resources
+            .values_mut()
+            .find(|(_, calendar)| calendar.contains(&format!("UID:{uid}")))
+            .unwrap();
+        *calendar = calendar.replace(from, to);
+        *etag += 100;
+        drop(resources);
+    };
+    db.set_tasks(&[new_task(1, "buy milk"), new_task(2, "write report")])
+        .unwrap();
+
+    // the url and user are saved, and the password can come from the config
+    assert!(caldav::Account::new(&mut db, None, None).is_err());
+    let user = "ann".to_string();
+    config::set(&mut db, "caldav-password", "wrong").unwrap();
+    // the mock server has no TLS, which has to be allowed
+    assert!(caldav::Account::new(&mut db, Some(&url), Some(&user)).is_err());
+    config::set(&mut db, "caldav-allow-http", "true").unwrap();
+    let account = caldav::Account::new(&mut db, None, None).unwrap();
+    assert!(caldav::sync(&mut db, &account, "sync caldav").is_err());
+    config::set(&mut db, "caldav-password", "secret").unwrap();
+    let account = caldav::Account::new(&mut db, None, None).unwrap();
+
+    // every task is uploaded the first time
+    let synced = caldav::sync(&mut db, &account, "sync caldav").unwrap();
+    assert_eq!(
+        (synced.uploaded, synced.deleted, synced.conflicts),
+        (2, 0, 0)
+    );
+    assert_eq!(server.lock().unwrap().len(), 2);
+    assert_eq!(
+        get_tasks(&db),
+        vec![new_task(1, "buy milk"), new_task(2, "write report")]
+    );
+
+    // changes on the server are pulled, and only the tasks changed here are pushed
+    edit("pls-1-1@pls", "SUMMARY:buy milk", "SUMMARY:buy oat milk");
+    server.lock().unwrap().insert(
+        "/tasks/nextcloud.ics".to_string(),
+        (1, "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:nextcloud\r\nSUMMARY:outline\r\nRELATED-TO:pls-2-2@pls\r\nEND:VTODO\r\nEND:VCALENDAR\r\n".to_string()),
+    );
+    let mut tasks = get_tasks(&db);
+    tasks[1].completed = true;
+    tasks[1].updated_at = Some(5);
+    db.set_tasks(&tasks).unwrap();
+    let synced = caldav::sync(&mut db, &account, "sync caldav").unwrap();
+    assert_eq!(
+        (synced.uploaded, synced.deleted, synced.conflicts),
+        (1, 0, 0)
+    );
+    assert_eq!(
+        titles(&get_tasks(&db)),
+        vec![
+            (1, "buy oat milk", false, None),
+            (2, "write report", true, None),
+            (3, "outline", false, Some(2)),
+        ]
+    );
+    assert!(server.lock().unwrap()["/tasks/pls-2-2@pls.ics"]
+        .1
+        .contains("STATUS:COMPLETED"));
+    let synced = caldav::sync(&mut db, &account, "sync caldav").unwrap();
+    assert_eq!(
+        (synced.uploaded, synced.deleted, synced.conflicts),
+        (0, 0, 0)
+    );
+    assert_eq!(synced.before, synced.after);
+
+    // tasks removed here are deleted from the server
+    let before = get_tasks(&db);
+    save_tasks(&mut db, "rm 1", &before, &before[1..]);
+    let synced = caldav::sync(&mut db, &account, "sync caldav").unwrap();
+    assert_eq!(
+        (synced.uploaded, synced.deleted, synced.conflicts),
+        (0, 1, 0)
+    );
+    assert_eq!(server.lock().unwrap().len(), 2);
+
+    // a task changed on both sides is a conflict, the last change winning
+    edit(
+        "pls-2-2@pls",
+        "SUMMARY:write report",
+        "SUMMARY:write the report",
+    );
+    let mut tasks = get_tasks(&db);
+    tasks[0].title = "send report".to_string();
+    tasks[0].updated_at = Some(10);
+    db.set_tasks(&tasks).unwrap();
+    let synced = caldav::sync(&mut db, &account, "sync caldav").unwrap();
+    assert_eq!(
+        (synced.uploaded, synced.deleted, synced.conflicts),
+        (1, 0, 1)
+    );
+    assert_eq!(get_tasks(&db)[0].title, "send report");
+    assert!(server.lock().unwrap()["/tasks/pls-2-2@pls.ics"]
+        .1
+        .contains("SUMMARY:send report"));
+
+    // resources that can't be read are skipped, the tasks they had staying as they are
+    server.lock().unwrap().insert(
+        "/tasks/broken.ics".to_string(),
+        (
+            1,
+            "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:broken\r\nEND:VTODO\r\nEND:VCALENDAR\r\n"
+                .to_string(),
+        ),
+    );
+    edit("nextcloud", "SUMMARY:outline\r\n", "");
+    let before = get_tasks(&db);
+    let synced = caldav::sync(&mut db, &account, "sync caldav").unwrap();
+    let mut skipped = synced
+        .skipped
+        .iter()
+        .map(|(href, _)| href.as_str())
+        .collect::<Vec<_>>();
+    skipped.sort_unstable();
+    assert_eq!(skipped, ["/tasks/broken.ics", "/tasks/nextcloud.ics"]);
+    assert_eq!(
+        (synced.uploaded, synced.deleted, synced.conflicts),
+        (0, 0, 0)
+    );
+    assert_eq!(get_tasks(&db), before);
+    assert_eq!(server.lock().unwrap().len(), 3);
+
+    // the ETag of an upload is read again from a server that doesn't give it, so that the next
+    // upload only replaces the version that was synced
+    let (url, server) = caldav_server(false);
+    let mut db = PickleDb::load_or_new(
+        "etags",
+        pickledb::PickleDbDumpPolicy::NeverDump,
+        pickledb::SerializationMethod::Json,
+    )
+    .expect("failed to create db");
+    db.set_tasks(&[new_task(1, "buy milk")]).unwrap();
+    config::set(&mut db, "caldav-password", "secret").unwrap();
+    config::set(&mut db, "caldav-allow-http", "true").unwrap();
+    let account = caldav::Account::new(&mut db, Some(&url), Some(&user)).unwrap();
+    caldav::sync(&mut db, &account, "sync caldav").unwrap();
+    let state = db.get::<serde_json::Value>("caldav-state").unwrap();
+    let etag = server.lock().unwrap()["/tasks/pls-1-1@pls.ics"].0;
+    assert_eq!(state["resources"][0]["etag"], format!("\"{etag}\""));
+}
+
+#[test]
+#[sealed_test]
+fn test_encryption() {
+    use crate::{
+        crypto::{self, Key},
+        migrate::migrate,
+        store::{self, JsonStore, Store},
+    };
+    use std::{fs, path::Path};
+
+    let key = Key::generate("correct horse").unwrap();
+    let encrypted = crypto::encrypt(&key, b"buy milk").unwrap();
+    assert!(crypto::is_encrypted(&encrypted));
+    assert_eq!(crypto::decrypt(&key, &encrypted).unwrap(), b"buy milk");
+    assert_ne!(crypto::encrypt(&key, b"buy milk").unwrap(), encrypted);
+    // another key, even from the same passphrase, or a changed file is refused
+    let other = Key::generate("correct horse").unwrap();
+    assert!(crypto::decrypt(&other, &encrypted).is_err());
+    let mut tampered = encrypted;
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(crypto::decrypt(&key, &tampered).is_err());
+
+    // pls.json is written encrypted, and read again with the key from PLS_KEY
+    std::env::set_var("PLS_STORAGE", "json");
+    let dir = Path::new("encrypted");
+    fs::create_dir(dir).unwrap();
+    let mut json = store::open_pls_json(dir).unwrap();
+    json.set_tasks(&[Task::new(&"call acme".into())]).unwrap();
+    json.set_key(Some(key.clone())).unwrap();
+    json.set_value("schema_version", 1.into()).unwrap();
+    let contents = fs::read(dir.join("pls.json")).unwrap();
+    assert!(crypto::is_encrypted(&contents));
+    assert!(!String::from_utf8_lossy(&contents).contains("acme"));
+    assert!(crypto::decrypt(&other, &contents).is_err());
+    std::env::set_var(crypto::KEY_VAR, key.to_hex());
+    let mut json = JsonStore::open(&dir.join("pls.json")).unwrap();
+    assert_eq!(json.tasks()[0].title, "call acme");
+    assert!(json.key() == Some(&key));
+
+    // so are backups made before an upgrade
+    let upgrade = migrate(&mut json, &dir.join("backups")).unwrap().unwrap();
+    assert!(crypto::is_encrypted(&fs::read(&upgrade.backup).unwrap()));
+    let backup = store::open_read_only(&upgrade.backup).unwrap();
+    assert_eq!(backup.tasks()[0].title, "call acme");
+
+    // and the other copies of tasks in the config directory, which new ones are encrypted like
+    fs::create_dir_all(dir.join("linked")).unwrap();
+    let journal = dir.join("linked/todo.md.json");
+    let mut linked = JsonStore::open_private(&journal, None).unwrap();
+    linked.set_tasks(&[Task::new(&"call acme".into())]).unwrap();
+    let old_backup = dir.join("backups/pls-v0-0.json");
+    fs::write(&old_backup, fs::read(&journal).unwrap()).unwrap();
+    store::encrypt_copies(dir, None, Some(&key)).unwrap();
+    for path in [&journal, &old_backup] {
+        assert!(crypto::is_encrypted(&fs::read(path).unwrap()));
+    }
+    let linked = JsonStore::open_private(&journal, Some(&key)).unwrap();
+    assert_eq!(linked.tasks()[0].title, "call acme");
+    let new_journal = dir.join("linked/new.json");
+    JsonStore::open_private(&new_journal, Some(&key)).unwrap();
+    assert!(crypto::is_encrypted(&fs::read(&new_journal).unwrap()));
+    // which sync would push in plain text
+    fs::create_dir(dir.join(".git")).unwrap();
+    let refused = crate::sync::sync(&mut json, dir, "sync").err().unwrap();
+    assert!(refused.contains("encrypted"));
+
+    // decrypting writes it in plain text again
+    store::encrypt_copies(dir, Some(&key), None).unwrap();
+    assert!(!crypto::is_encrypted(&fs::read(&journal).unwrap()));
+    assert!(!crypto::is_encrypted(&fs::read(&old_backup).unwrap()));
+    json.set_key(None).unwrap();
+    std::env::remove_var(crypto::KEY_VAR);
+    let contents = fs::read(dir.join("pls.json")).unwrap();
+    assert!(String::from_utf8_lossy(&contents).contains("acme"));
+    let json = JsonStore::open(&dir.join("pls.json")).unwrap();
+    assert!(json.key().is_none());
+    assert_eq!(json.tasks()[0].title, "call acme");
+    std::env::remove_var("PLS_STORAGE");
+}
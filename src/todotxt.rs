@@ -0,0 +1,199 @@
+use time::{macros::format_description, Date, OffsetDateTime, Time, UtcOffset};
+
+use crate::{
+    due::{parse_iso_date, END_OF_DAY},
+    priority::Priority,
+    Task,
+};
+
+/// Write tasks as todo.txt, one task per line.
+pub fn export(tasks: &[Task], offset: UtcOffset) -> String {
+    tasks.iter().fold(String::new(), |mut text, task| {
+        text.push_str(&to_line(task, offset));
+        text.push('\n');
+        text
+    })
+}
+
+/// Read tasks from todo.txt. The tasks have no id yet.
+pub fn import(text: &str, offset: UtcOffset) -> Vec<Task> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| parse_line(line, offset))
+        .collect()
+}
+
+/// A task as a todo.txt line, ex: "(A) 2024-05-01 call mum +family @phone due:2024-05-17".
+/// The project is written as a +project and tags as @contexts.
+pub fn to_line(task: &Task, offset: UtcOffset) -> String {
+    let date = |timestamp: Option<i64>| timestamp.and_then(|t| format_date(t, offset));
+    let mut words = Vec::new();
+    if task.completed {
+        words.push("x".to_string());
+        // a single date after the x is the completion date
+        words.extend(date(task.completed_at));
+        if task.completed_at.is_some() {
+            words.extend(date(task.created_at));
+        }
+    } else {
+        words.extend(letter(task).map(|letter| format!("({letter})")));
+        words.extend(date(task.created_at));
+    }
+    words.push(task.title.clone());
+    words.extend(task.project.iter().map(|project| format!("+{project}")));
+    words.extend(task.tags.iter().map(|tag| format!("@{tag}")));
+    words.extend(date(task.due).map(|due| format!("due:{due}")));
+    // completed tasks keep their priority as a key/value, since (A) can't follow the x
+    if task.completed {
+        words.extend(letter(task).map(|letter| format!("pri:{letter}")));
+    }
+    // a letter below C is kept in extra, and was written above
+    let kept = letter(task)
+        .filter(|&letter| letter > 'C')
+        .map(|letter| format!("pri:{letter}"));
+    words.extend(
+        task.extra
+            .iter()
+            .filter(|&word| Some(word) != kept.as_ref())
+            .cloned(),
+    );
+    words.join(" ")
+}
+
+/// Parse a todo.txt line. Words pls doesn't understand, such as `t:` or `rec:` key/values and
+/// any +projects after the first, are kept in `extra` so they survive a round trip.
+pub fn parse_line(line: &str, offset: UtcOffset) -> Task {
+    let timestamp = |date: Date, time: Time| date.with_time(time).assume_offset(offset);
+    let mut task = Task::default();
+    let mut words = line.split_whitespace().peekable();
+
+    if words.next_if_eq(&"x").is_some() {
+        task.completed = true;
+        let dates = [
+            words.next_if(|word| parse_iso_date(word).is_some()),
+            words.next_if(|word| parse_iso_date(word).is_some()),
+        ];
+        task.completed_at = dates[0]
+            .and_then(parse_iso_date)
+            .map(|date| timestamp(date, Time::MIDNIGHT).unix_timestamp());
+        task.created_at = dates[1]
+            .and_then(parse_iso_date)
+            .map(|date| timestamp(date, Time::MIDNIGHT).unix_timestamp());
+    } else {
+        let priority = |word: &str| {
+            word.strip_prefix('(')
+                .and_then(|w| w.strip_suffix(')'))
+                .and_then(parse_priority)
+        };
+        if let Some(word) = words.next_if(|word| priority(word).is_some()) {
+            task.priority = priority(word);
+            keep_letter(&mut task, &word[1..2]);
+        }
+        task.created_at = words
+            .next_if(|word| parse_iso_date(word).is_some())
+            .and_then(parse_iso_date)
+            .map(|date| timestamp(date, Time::MIDNIGHT).unix_timestamp());
+    }
+
+    // key/values pls doesn't know are only taken out of the title after its last word, so
+    // that words such as "re:foo" in the middle of it stay where they are
+    let words = words.collect::<Vec<_>>();
+    let title_end = words
+        .iter()
+        .rposition(|word| !is_project_or_context(word) && key_value(word).is_none())
+        .map_or(0, |last| last + 1);
+    let mut title = Vec::new();
+    for (n, word) in words.into_iter().enumerate() {
+        if let Some(project) = word.strip_prefix('+').filter(|p| !p.is_empty()) {
+            if task.project.is_none() {
+                task.project = Some(project.to_lowercase());
+            } else {
+                task.extra.push(word.to_string());
+            }
+        } else if let Some(tag) = word.strip_prefix('@').filter(|t| !t.is_empty()) {
+            let tag = tag.to_lowercase();
+            if !task.tags.contains(&tag) {
+                task.tags.push(tag);
+            }
+        } else if let Some((key, value)) = key_value(word) {
+            match key {
+                "due" if parse_iso_date(value).is_some() => {
+                    task.due = parse_iso_date(value)
+                        .map(|date| timestamp(date, END_OF_DAY).unix_timestamp());
+                }
+                "pri" if task.priority.is_none() && parse_priority(value).is_some() => {
+                    task.priority = parse_priority(value);
+                    keep_letter(&mut task, value);
+                }
+                _ if n < title_end => title.push(word),
+                _ => task.extra.push(word.to_string()),
+            }
+        } else {
+            title.push(word);
+        }
+    }
+    task.title = title.join(" ");
+    task.updated_at = task.completed_at.or(task.created_at);
+    task
+}
+
+// todo.txt priorities are letters, A being the most important. pls only has three levels, so
+// anything below C is low, and the letter is kept in `extra` as pri:<letter> to be written again
+fn letter(task: &Task) -> Option<char> {
+    let priority = task.priority?;
+    let kept = task
+        .extra
+        .iter()
+        .find_map(|word| word.strip_prefix("pri:"))
+        .filter(|letter| parse_priority(letter) == Some(priority))
+        .and_then(|letter| letter.chars().next());
+    Some(kept.unwrap_or(match priority {
+        Priority::High => 'A',
+        Priority::Medium => 'B',
+        Priority::Low => 'C',
+    }))
+}
+
+fn keep_letter(task: &mut Task, letter: &str) {
+    if letter > "C" {
+        task.extra.push(format!("pri:{letter}"));
+    }
+}
+
+fn parse_priority(letter: &str) -> Option<Priority> {
+    match letter {
+        "A" => Some(Priority::High),
+        "B" => Some(Priority::Medium),
+        _ if letter.len() == 1 && letter.chars().all(|c| c.is_ascii_uppercase()) => {
+            Some(Priority::Low)
+        }
+        _ => None,
+    }
+}
+
+fn is_project_or_context(word: &str) -> bool {
+    word.len() > 1 && word.starts_with(['+', '@'])
+}
+
+// a key:value word, whose key is a name and whose value isn't a number or a time, so that
+// "10:30" or "ratio 2:1" are part of the title, and so are urls such as https://example.com
+fn key_value(word: &str) -> Option<(&str, &str)> {
+    let (key, value) = word.split_once(':')?;
+    let is_name = key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let is_number = value
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == ':' || c == '.');
+    let valid = is_name && !value.is_empty() && !is_number && !value.starts_with("//");
+    valid.then_some((key, value))
+}
+
+fn format_date(timestamp: i64, offset: UtcOffset) -> Option<String> {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .ok()?
+        .to_offset(offset)
+        .format(format_description!("[year]-[month]-[day]"))
+        .ok()
+}
@@ -1,16 +1,15 @@
 use std::{process::Stdio, sync::Arc, time::Duration};
 
 use native_tls::TlsConnector;
-use pickledb::PickleDb;
 use spinach::Spinach;
 
-use crate::get_time;
+use crate::{get_time, store::Store};
 
-pub(crate) fn get_weather(db: &mut PickleDb, force_refresh: bool) -> Result<String, String> {
+pub(crate) fn get_weather(db: &mut dyn Store, force_refresh: bool) -> Result<String, String> {
     // represent current unix timestamp
     let timestamp_current = get_time().unix_timestamp();
     // closure that fetches the weather and caches it.
-    let fetch_and_cache_weather = |db: &mut PickleDb| -> Result<String, String> {
+    let fetch_and_cache_weather = |db: &mut dyn Store| -> Result<String, String> {
         // if specific location is not set, the default for `String` will be used (an empty string).
         // thus the request will be to "https://wttr.in/?format=%l:+%C+%c+%t" which is the URL structure
         // for letting the server geolocate based on IP address.
@@ -42,39 +41,36 @@ pub(crate) fn get_weather(db: &mut PickleDb, force_refresh: bool) -> Result<Stri
 
         s.text("Caching weather...");
 
-        db.set("weather-cached", &weather_info)
-            .expect("Failed to set cached weather");
-
-        s.text("Caching weather timestamp...");
-
-        db.set("weather-timestamp", &timestamp_current)
+        db.cache_weather(&weather_info, timestamp_current)
             .expect("Failed to set cached weather");
 
         s.succeed("Weather retrieved");
         Ok(weather_info)
     };
     // if weather-timestamp is set (ie previous cache success)
-    if let Some(timestamp) = db.get::<i64>("weather-timestamp") {
+    if let Some(timestamp) = db.weather_timestamp() {
         // if manually forcing a refresh
         if force_refresh {
             // force refresh and block thread when forced
             fetch_and_cache_weather(db)
             
         } // if timestamp is outdated
-        else if timestamp_current - timestamp > 900 || !db.exists("weather-cached") {
+        else if timestamp_current - timestamp > 900 || db.cached_weather().is_none() {
             // if refresh isn't forced, but it is outdated or a cache doesn't exist, spawn new
             // process to update in the background, so that the terminal isn't blocked by a weather
             // update, but when the user next uses `pls`, they will receive up-to-date weather.
             drop(
                 std::process::Command::new("pls")
                     .arg("-r")
+                    // so that it never asks for the passphrase of an encrypted pls.json
+                    .stdin(Stdio::null())
                     .stdout(Stdio::null())
                     .stderr(Stdio::null())
                     .spawn(),
             );
             // then report a cached version (and if there is none, just use an empty string. The next time it will contain actual weather)
             Ok(db
-                .get::<String>("weather-cached")
+                .cached_weather()
                 .map(|s| {
                     format!(
                         "{} ({} min outdated, will be updated on next launch)",
@@ -85,7 +81,7 @@ pub(crate) fn get_weather(db: &mut PickleDb, force_refresh: bool) -> Result<Stri
                 .unwrap_or_default())
         } else {
             // if the timestamp is not outdated simply load cached weather
-            db.get::<String>("weather-cached").map_or_else(
+            db.cached_weather().map_or_else(
                 || Err(format!("Failed to load cached weather.",)),
                 |s| Ok(s),
             )